@@ -0,0 +1,248 @@
+//! An async HTTP client for corolla servers.
+//!
+//! [`client!`] generates a client typed for a particular spec.json at compile time,
+//! so calling a query or passing an arg that isn't in the spec is a compile error:
+//!
+//! ```ignore
+//! corolla_client::client!("spec.json");
+//!
+//! let corolla = Corolla::new("http://localhost:50000");
+//! corolla
+//!     .write_write01(&WriteWrite01Args { vacation_spot: "avon".into() })
+//!     .await?;
+//! let rows: Vec<ReadRead01Row> = corolla.read_read01(&ReadRead01Args {}).await?;
+//! ```
+//!
+//! [`Client`] calls queries by name, for specs that aren't known at compile time.
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+// lets the code generated by `client!` name this crate from inside it, in tests
+extern crate self as corolla_client;
+
+pub use corolla_macros::client;
+// the code generated by `client!` uses these, so callers don't need to depend on them
+#[doc(hidden)]
+pub use serde;
+#[doc(hidden)]
+pub use serde_json;
+
+/// An error calling a query.
+#[derive(Debug)]
+pub enum Error {
+    /// The request couldn't be sent, or its response couldn't be read.
+    Http(reqwest::Error),
+    /// The server responded with an error.
+    Server {
+        status: u16,
+        code: String,
+        message: String,
+        details: Option<Value>,
+    },
+    /// The response doesn't have the shape the spec declares.
+    Decode(serde_json::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "request failed: {e}"),
+            Error::Server {
+                status,
+                code,
+                message,
+                ..
+            } => write!(f, "server responded {status} {code}: {message}"),
+            Error::Decode(e) => write!(f, "unexpected response: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Decode(e)
+    }
+}
+
+/// The body of an error response.
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: String,
+    message: String,
+    details: Option<Value>,
+}
+
+/// The result of a write query.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct WriteResult<Row> {
+    pub rows_affected: u64,
+    /// The rowid of the last row the query inserted, or `None` if it inserted no rows.
+    pub last_insert_rowid: Option<i64>,
+    /// The rows returned by a `RETURNING` clause, if any.
+    pub rows: Option<Vec<Row>>,
+}
+
+/// The body of a write response, before its rows are decoded.
+#[derive(Deserialize)]
+struct RawWriteResult {
+    rows_affected: u64,
+    last_insert_rowid: Option<i64>,
+    rows: Option<Vec<Vec<Value>>>,
+}
+
+/// Decodes result rows, keyed by the header row's column names.
+fn decode_rows<Row: DeserializeOwned>(rows: Vec<Vec<Value>>) -> Result<Vec<Row>, Error> {
+    let mut rows = rows.into_iter();
+    let header: Vec<String> = match rows.next() {
+        Some(header) => header
+            .into_iter()
+            .map(|col| match col {
+                Value::String(col) => col,
+                col => col.to_string(),
+            })
+            .collect(),
+        None => return Ok(vec![]),
+    };
+    rows.map(|row| {
+        let row: Map<String, Value> = header.iter().cloned().zip(row).collect();
+        Ok(serde_json::from_value(Value::Object(row))?)
+    })
+    .collect()
+}
+
+/// A client that calls a corolla server's queries by name.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl Client {
+    /// Arguments:
+    ///
+    /// * `base_url` - The corolla server's URL, including any route base, e.g. `http://localhost:50000/api`.
+    pub fn new(base_url: &str) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    /// Like [`Client::new`], but sends requests with a configured `reqwest::Client`, e.g. one with timeouts.
+    pub fn with_http_client(http: reqwest::Client, base_url: &str) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    /// Sends a request, and turns an error response into an [`Error::Server`].
+    async fn send<T: DeserializeOwned>(&self, req: reqwest::RequestBuilder) -> Result<T, Error> {
+        let res = req.send().await?;
+        let status = res.status();
+        let body = res.bytes().await?;
+        if !status.is_success() {
+            let body: ErrorBody = serde_json::from_slice(&body)?;
+            return Err(Error::Server {
+                status: status.as_u16(),
+                code: body.code,
+                message: body.message,
+                details: body.details,
+            });
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Runs a read query, and decodes each result row into a `Row`.
+    ///
+    /// Arguments:
+    ///
+    /// * `query` - The read query's name.
+    /// * `args` - The query's args, sent as URL query parameters.
+    pub async fn read<Args: Serialize, Row: DeserializeOwned>(
+        &self,
+        query: &str,
+        args: &Args,
+    ) -> Result<Vec<Row>, Error> {
+        let req = self
+            .http
+            .get(format!("{}/read/{query}", self.base_url))
+            .query(args);
+        decode_rows(self.send(req).await?)
+    }
+
+    /// Runs a write query, and decodes any rows it returns into `Row`s.
+    ///
+    /// Arguments:
+    ///
+    /// * `query` - The write query's name.
+    /// * `args` - The query's args, sent as a JSON body.
+    pub async fn write<Args: Serialize, Row: DeserializeOwned>(
+        &self,
+        query: &str,
+        args: &Args,
+    ) -> Result<WriteResult<Row>, Error> {
+        let req = self
+            .http
+            .post(format!("{}/write/{query}", self.base_url))
+            .json(args);
+        let res: RawWriteResult = self.send(req).await?;
+        Ok(WriteResult {
+            rows_affected: res.rows_affected,
+            last_insert_rowid: res.last_insert_rowid,
+            rows: res.rows.map(decode_rows).transpose()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    client!("../examples/example_spec.json");
+
+    #[tokio::test]
+    /// the generated client calls the spec's queries with typed args and rows
+    async fn generated_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("client.sqlite3");
+        let spec = corolla::read_spec("../examples/example_spec.json").unwrap();
+        let db = corolla::DB::from_spec(db.to_str().unwrap(), &spec, corolla::DbOptions::default())
+            .await
+            .unwrap();
+        let router = corolla::RouterBuilder::new(db).route_base("/api").build();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+        let corolla = Corolla::new(&format!("http://{addr}/api/"));
+        let rows = corolla.read_read01(&ReadRead01Args {}).await;
+        assert_eq!(rows.unwrap(), vec![]);
+        let res = corolla
+            .write_write01(&WriteWrite01Args {
+                vacation_spot: "avon".into(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(res.rows_affected, 1);
+        assert_eq!(
+            corolla.read_read01(&ReadRead01Args {}).await.unwrap(),
+            vec![ReadRead01Row {
+                vacation_spot: "avon".into()
+            }]
+        );
+        let client = Client::new(&format!("http://{addr}/api"));
+        match client.read::<_, Value>("read02", &ReadRead01Args {}).await {
+            Err(Error::Server { status, code, .. }) => {
+                assert_eq!((status, code.as_str()), (404, "query_not_found"))
+            }
+            res => panic!("expected a server error, got {res:?}"),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Macros that generate typed corolla clients from a spec.json at compile time.
+//! Use them through the `corolla-client` crate, which the generated code depends on.
+use corolla_spec::{read_spec, Arg, ArgType, ResultFormat, Spec};
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use std::{collections::HashMap, path::PathBuf};
+use syn::{parse_macro_input, LitStr};
+
+/// Turns a name from the spec into a valid Rust field or method name.
+fn snake_ident(name: &str) -> Ident {
+    let mut ident: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '_',
+        })
+        .collect();
+    if ident.is_empty() || ident.starts_with(|c: char| c.is_ascii_digit()) {
+        ident.insert(0, '_');
+    }
+    match ident.as_str() {
+        // keywords that can't be raw identifiers
+        "crate" | "self" | "super" | "_" => format_ident!("{ident}_"),
+        _ if syn::parse_str::<Ident>(&ident).is_err() => Ident::new_raw(&ident, Span::call_site()),
+        _ => Ident::new(&ident, Span::call_site()),
+    }
+}
+
+/// Turns a name from the spec into a valid Rust type name, e.g. `get-people` into `GetPeople`.
+fn camel_name(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
+
+/// Checks that no two names from the spec generate the same Rust name.
+///
+/// Arguments:
+///
+/// * `generated` - Each generated Rust name, and what in the spec it was generated from.
+fn check_unique(generated: impl Iterator<Item = (String, String)>) -> Result<(), String> {
+    let mut seen = HashMap::new();
+    for (ident, from) in generated {
+        if let Some(other) = seen.insert(ident.clone(), from.clone()) {
+            return Err(format!("{other} and {from} would both generate `{ident}`"));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that no two args or columns of a query generate the same field name.
+fn check_fields<'a>(names: impl Iterator<Item = &'a String>, what: &str) -> Result<(), String> {
+    check_unique(names.map(|name| (snake_ident(name).to_string(), format!("{what} `{name}`"))))
+}
+
+/// A struct field for each name, type and extra attributes,
+/// renamed back to the name in the spec where its Rust name differs.
+fn fields<'a>(
+    fields: impl Iterator<Item = (&'a str, TokenStream2, TokenStream2)>,
+) -> Vec<TokenStream2> {
+    fields
+        .map(|(name, ty, attrs)| {
+            let ident = snake_ident(name);
+            let rename = match ident.to_string().trim_start_matches("r#") == name {
+                true => quote!(),
+                false => quote!(#[serde(rename = #name)]),
+            };
+            quote! {
+                #rename
+                #attrs
+                pub #ident: #ty
+            }
+        })
+        .collect()
+}
+
+/// The Rust type of an argument's value.
+///
+/// Arguments:
+///
+/// * `arg` - The argument.
+/// * `read` - Whether the argument is sent in a read query's URL, where untyped and JSON values are sent as text.
+fn arg_type(arg: &Arg, read: bool) -> TokenStream2 {
+    let ty = match arg.r#type {
+        Some(ArgType::Integer) => quote!(i64),
+        Some(ArgType::Real) => quote!(f64),
+        Some(ArgType::Boolean) => quote!(bool),
+        Some(ArgType::Text | ArgType::Blob | ArgType::Date) => quote!(::std::string::String),
+        Some(ArgType::Json) | None if read => quote!(::std::string::String),
+        Some(ArgType::Json) | None => quote!(::corolla_client::serde_json::Value),
+    };
+    match arg.optional {
+        true => quote!(::std::option::Option<#ty>),
+        false => ty,
+    }
+}
+
+/// An arguments struct, with optional arguments left out of requests when they're `None`.
+fn args_struct(ident: &Ident, args: &[Arg], read: bool) -> TokenStream2 {
+    let fields = fields(args.iter().map(|a| {
+        let skip = match a.optional {
+            true => quote!(#[serde(skip_serializing_if = "::std::option::Option::is_none")]),
+            false => quote!(),
+        };
+        (a.name.as_str(), arg_type(a, read), skip)
+    }));
+    quote! {
+        #[derive(::corolla_client::serde::Serialize, ::std::fmt::Debug, ::std::clone::Clone, ::std::default::Default)]
+        #[serde(crate = "::corolla_client::serde")]
+        pub struct #ident {
+            #(#fields,)*
+        }
+    }
+}
+
+/// A result row struct with one field per column.
+fn row_struct(ident: &Ident, cols: &[String], format: ResultFormat) -> TokenStream2 {
+    let ty = match format {
+        ResultFormat::Typed => quote!(::corolla_client::serde_json::Value),
+        ResultFormat::Strings => quote!(::std::string::String),
+    };
+    let fields = fields(cols.iter().map(|c| (c.as_str(), ty.clone(), quote!())));
+    quote! {
+        #[derive(::corolla_client::serde::Deserialize, ::std::fmt::Debug, ::std::clone::Clone, ::std::cmp::PartialEq)]
+        #[serde(crate = "::corolla_client::serde")]
+        pub struct #ident {
+            #(#fields,)*
+        }
+    }
+}
+
+/// Generates the client for a spec.
+///
+/// Arguments:
+///
+/// * `spec` - The spec whose queries the client calls.
+/// * `path` - The spec's filepath, so the client is regenerated when the spec changes.
+fn expand(spec: &Spec, path: &str) -> Result<TokenStream2, String> {
+    let mut items = vec![];
+    let mut methods = vec![];
+    let mut generated = vec![];
+    let mut names: Vec<&String> = spec.queries.read.keys().collect();
+    names.sort();
+    for name in names {
+        let query = &spec.queries.read[name];
+        let what = format!("read query `{name}`");
+        check_fields(query.args.iter().map(|a| &a.name), &format!("{what}'s arg"))?;
+        check_fields(query.cols.iter(), &format!("{what}'s column"))?;
+        let args = format_ident!("Read{}Args", camel_name(name));
+        let row = format_ident!("Read{}Row", camel_name(name));
+        let method = format_ident!(
+            "read_{}",
+            snake_ident(name).to_string().trim_start_matches("r#")
+        );
+        for ident in [&args, &row, &method] {
+            generated.push((ident.to_string(), what.clone()));
+        }
+        let doc = format!(
+            "Runs the `{name}` read query:\n\n```sql\n{}\n```",
+            query.sql_template
+        );
+        items.push(args_struct(&args, &query.args, true));
+        items.push(row_struct(&row, &query.cols, query.format));
+        methods.push(quote! {
+            #[doc = #doc]
+            pub async fn #method(&self, args: &#args) -> ::std::result::Result<::std::vec::Vec<#row>, ::corolla_client::Error> {
+                self.client.read(#name, args).await
+            }
+        });
+    }
+    let mut names: Vec<&String> = spec.queries.write.keys().collect();
+    names.sort();
+    for name in names {
+        let query = &spec.queries.write[name];
+        let what = format!("write query `{name}`");
+        check_fields(query.args.iter().map(|a| &a.name), &format!("{what}'s arg"))?;
+        let args = format_ident!("Write{}Args", camel_name(name));
+        let method = format_ident!(
+            "write_{}",
+            snake_ident(name).to_string().trim_start_matches("r#")
+        );
+        let sql = match query.statements.is_empty() {
+            true => query.sql_template.clone(),
+            false => query
+                .statements
+                .iter()
+                .map(|s| s.sql_template.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        generated.push((args.to_string(), what.clone()));
+        generated.push((method.to_string(), what.clone()));
+        let doc = format!("Runs the `{name}` write query:\n\n```sql\n{sql}\n```");
+        items.push(args_struct(&args, &query.args, false));
+        let row = match &query.cols {
+            Some(cols) => {
+                check_fields(cols.iter(), &format!("{what}'s column"))?;
+                let row = format_ident!("Write{}Row", camel_name(name));
+                generated.push((row.to_string(), what.clone()));
+                items.push(row_struct(&row, cols, ResultFormat::Typed));
+                quote!(#row)
+            }
+            None => quote! {
+                ::std::collections::HashMap<::std::string::String, ::corolla_client::serde_json::Value>
+            },
+        };
+        methods.push(quote! {
+            #[doc = #doc]
+            pub async fn #method(&self, args: &#args) -> ::std::result::Result<::corolla_client::WriteResult<#row>, ::corolla_client::Error> {
+                self.client.write(#name, args).await
+            }
+        });
+    }
+    check_unique(generated.into_iter())?;
+    Ok(quote! {
+        // makes cargo rebuild the client when the spec changes
+        const _: &[u8] = include_bytes!(#path);
+
+        #(#items)*
+
+        /// A client with one typed method per query in the spec.
+        #[derive(::std::fmt::Debug, ::std::clone::Clone)]
+        pub struct Corolla {
+            client: ::corolla_client::Client,
+        }
+
+        impl Corolla {
+            /// Arguments:
+            ///
+            /// * `base_url` - The corolla server's URL, including any route base, e.g. `http://localhost:50000/api`.
+            pub fn new(base_url: &str) -> Self {
+                Self { client: ::corolla_client::Client::new(base_url) }
+            }
+
+            /// Wraps an untyped client, e.g. one built with a custom `reqwest::Client`.
+            pub fn from_client(client: ::corolla_client::Client) -> Self {
+                Self { client }
+            }
+
+            #(#methods)*
+        }
+    })
+}
+
+/// Generates a typed client from a spec.json at compile time:
+/// a `Corolla` struct with a `read_<name>` method per read query and a `write_<name>` method per write query,
+/// and an arguments struct and a row struct for each query.
+/// The filepath is relative to the calling crate's `Cargo.toml`.
+///
+/// ```ignore
+/// corolla_client::client!("spec.json");
+///
+/// let rows = Corolla::new("http://localhost:50000")
+///     .read_read01(&ReadRead01Args {})
+///     .await?;
+/// ```
+#[proc_macro]
+pub fn client(input: TokenStream) -> TokenStream {
+    let lit = parse_macro_input!(input as LitStr);
+    let path = match std::env::var("CARGO_MANIFEST_DIR") {
+        Ok(dir) => PathBuf::from(dir).join(lit.value()),
+        Err(_) => PathBuf::from(lit.value()),
+    };
+    let expanded = match read_spec(&path) {
+        Ok(spec) => expand(&spec, &path.to_string_lossy())
+            .map_err(|e| format!("could not generate a client for {}: {e}", path.display())),
+        Err(e) => Err(format!("could not read {}: {e}", path.display())),
+    };
+    match expanded {
+        Ok(tokens) => tokens.into(),
+        Err(e) => syn::Error::new(lit.span(), e).to_compile_error().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// names from the spec become valid Rust names
+    fn names() {
+        assert_eq!(snake_ident("vacation_spot").to_string(), "vacation_spot");
+        assert_eq!(snake_ident("Favorite Color").to_string(), "favorite_color");
+        assert_eq!(snake_ident("type").to_string(), "r#type");
+        assert_eq!(snake_ident("self").to_string(), "self_");
+        assert_eq!(snake_ident("2fa").to_string(), "_2fa");
+        assert_eq!(camel_name("get-people"), "GetPeople");
+        assert_eq!(camel_name("read01"), "Read01");
+    }
+
+    #[test]
+    /// names from the spec that would generate the same Rust name are refused
+    fn collisions() {
+        let spec = |queries: serde_json::Value| -> Spec {
+            serde_json::from_value(serde_json::json!({
+                "spec_version": [1, 0, 0],
+                "version": [1],
+                "init": [],
+                "queries": queries,
+                "conversions": []
+            }))
+            .unwrap()
+        };
+        let read = |args: serde_json::Value| serde_json::json!({ "sql_template": "select 1;", "args": args, "cols": ["x"] });
+        let ok = spec(serde_json::json!({
+            "read": { "people": read(serde_json::json!(["a", "b"])) },
+            "write": { "people": { "sql_template": "delete from t;", "args": [] } }
+        }));
+        assert!(expand(&ok, "spec.json").is_ok());
+        let separators = spec(serde_json::json!({
+            "read": { "get-people": read(serde_json::json!([])), "get_people": read(serde_json::json!([])) },
+            "write": {}
+        }));
+        assert_eq!(
+            expand(&separators, "spec.json").unwrap_err(),
+            "read query `get-people` and read query `get_people` would both generate `ReadGetPeopleArgs`"
+        );
+        let types = spec(serde_json::json!({
+            "read": { "getPeople": read(serde_json::json!([])), "get_people": read(serde_json::json!([])) },
+            "write": {}
+        }));
+        assert_eq!(
+            expand(&types, "spec.json").unwrap_err(),
+            "read query `getPeople` and read query `get_people` would both generate `ReadGetPeopleArgs`"
+        );
+        let args = spec(serde_json::json!({
+            "read": { "people": read(serde_json::json!(["first name", "first_name"])) },
+            "write": {}
+        }));
+        assert_eq!(
+            expand(&args, "spec.json").unwrap_err(),
+            "read query `people`'s arg `first name` and read query `people`'s arg `first_name` would both generate `first_name`"
+        );
+    }
+}
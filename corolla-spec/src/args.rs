@@ -0,0 +1,361 @@
+/// This file contains query arg declarations, and methods for checking an argument's value against them.
+use super::value::SqlValue;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use regex::Regex;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::sync::LazyLock;
+
+/// The type an argument's value is coerced to before it is bound.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgType {
+    /// Bound as INTEGER.
+    Integer,
+    /// Bound as REAL.
+    Real,
+    /// Bound as TEXT.
+    Text,
+    /// `true`/`false`, `"true"`/`"false"` or `1`/`0`. Bound as INTEGER 1 or 0.
+    Boolean,
+    /// A base64 string. Bound as BLOB.
+    Blob,
+    /// A JSON document; strings are parsed as JSON text. Bound as minified JSON TEXT.
+    Json,
+    /// An ISO 8601 date (`2024-07-04`) or datetime (`2024-07-04T12:00:00Z`). Bound as TEXT.
+    Date,
+}
+
+/// A regular expression in a spec.json, compiled when the spec is loaded.
+#[derive(Clone, Debug)]
+pub struct Pattern(Regex);
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+/// A query parameter declaration.
+/// In spec.json, this is either a bare name (`"id"`) or an object such as
+/// `{ "name": "id", "type": "integer", "min": 1 }` or `{ "name": "notes", "optional": true, "default": null }`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(try_from = "ArgDecl")]
+pub struct Arg {
+    /// The parameter's name, i.e. its key in a request's arguments.
+    pub name: String,
+    /// Whether requests may leave this argument out.
+    pub optional: bool,
+    /// The value bound when an optional argument is left out. `null` is bound as SQL NULL.
+    pub default: Value,
+    /// The type the argument is coerced to. Untyped arguments are bound as they were sent.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<ArgType>,
+    /// A regular expression the argument's text must match.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<Pattern>,
+    /// The only values the argument may take.
+    #[serde(rename = "enum", skip_serializing_if = "Option::is_none")]
+    pub r#enum: Option<Vec<Value>>,
+    /// The smallest value an integer or real argument may take.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// The largest value an integer or real argument may take.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// The maximum length of a text argument in characters, or of a blob argument in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+}
+
+/// The forms an `Arg` can take in spec.json.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ArgDecl {
+    Name(String),
+    Full(FullArgDecl),
+}
+
+/// The object form of an `Arg` in spec.json.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FullArgDecl {
+    name: String,
+    #[serde(default)]
+    optional: bool,
+    #[serde(default)]
+    default: Value,
+    #[serde(rename = "type")]
+    r#type: Option<ArgType>,
+    regex: Option<String>,
+    #[serde(rename = "enum")]
+    r#enum: Option<Vec<Value>>,
+    min: Option<f64>,
+    max: Option<f64>,
+    max_length: Option<usize>,
+}
+
+impl TryFrom<ArgDecl> for Arg {
+    type Error = String;
+
+    fn try_from(decl: ArgDecl) -> Result<Self, Self::Error> {
+        let arg = match decl {
+            ArgDecl::Name(name) => Arg::new(&name),
+            ArgDecl::Full(decl) => Arg {
+                regex: match decl.regex {
+                    Some(r) => {
+                        Some(Pattern(Regex::new(&r).map_err(|e| {
+                            format!("bad regex for arg {}: {e}", decl.name)
+                        })?))
+                    }
+                    None => None,
+                },
+                name: decl.name,
+                optional: decl.optional,
+                default: decl.default,
+                r#type: decl.r#type,
+                r#enum: decl.r#enum,
+                min: decl.min,
+                max: decl.max,
+                max_length: decl.max_length,
+            },
+        };
+        // catch bad defaults when the spec is loaded, not when a request leaves the arg out
+        if !arg.default.is_null() {
+            arg.coerce(&arg.default)
+                .map_err(|e| format!("bad default for arg {}: {e}", arg.name))?;
+        }
+        Ok(arg)
+    }
+}
+
+impl Arg {
+    /// A required, untyped arg.
+    pub fn new(name: &str) -> Self {
+        Arg {
+            name: name.to_owned(),
+            optional: false,
+            default: Value::Null,
+            r#type: None,
+            regex: None,
+            r#enum: None,
+            min: None,
+            max: None,
+            max_length: None,
+        }
+    }
+
+    /// Coerces a value to this arg's type, and checks it against this arg's constraints.
+    /// Returns a description of the problem if the value is not acceptable.
+    pub fn coerce(&self, val: &Value) -> Result<SqlValue, String> {
+        if val.is_null() {
+            return if self.optional {
+                Ok(SqlValue::Null)
+            } else {
+                Err("must not be null".to_owned())
+            };
+        }
+        if let Some(regex) = &self.regex {
+            let text = match val {
+                Value::String(s) => s.clone(),
+                _ => val.to_string(),
+            };
+            if !regex.0.is_match(&text) {
+                return Err(format!("must match {}", regex.0.as_str()));
+            }
+        }
+        let res = match self.r#type {
+            None => SqlValue::from(val),
+            Some(t) => coerce_to(t, val)?,
+        };
+        match &res {
+            SqlValue::Integer(i) => self.check_range(*i as f64)?,
+            SqlValue::Real(f) => self.check_range(*f)?,
+            SqlValue::Text(s) => self.check_length(s.chars().count())?,
+            SqlValue::Blob(b) => self.check_length(b.len())?,
+            SqlValue::Null => (),
+        }
+        if let Some(allowed) = &self.r#enum {
+            let allowed: Vec<SqlValue> = allowed
+                .iter()
+                .filter_map(|a| match self.r#type {
+                    None => Some(SqlValue::from(a)),
+                    Some(t) => coerce_to(t, a).ok(),
+                })
+                .collect();
+            if !allowed.contains(&res) {
+                return Err(format!(
+                    "must be one of {}",
+                    Value::from(self.r#enum.clone().unwrap_or_default())
+                ));
+            }
+        }
+        Ok(res)
+    }
+
+    fn check_range(&self, x: f64) -> Result<(), String> {
+        if let Some(min) = self.min {
+            if x < min {
+                return Err(format!("must be at least {min}"));
+            }
+        }
+        if let Some(max) = self.max {
+            if x > max {
+                return Err(format!("must be at most {max}"));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_length(&self, len: usize) -> Result<(), String> {
+        match self.max_length {
+            Some(max) if len > max => Err(format!("must be at most {max} long")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Coerces a non-null value to a type.
+fn coerce_to(t: ArgType, val: &Value) -> Result<SqlValue, String> {
+    match (t, val) {
+        (ArgType::Integer, Value::Number(n)) => n
+            .as_i64()
+            .map(SqlValue::Integer)
+            .ok_or_else(|| "must be an integer".to_owned()),
+        (ArgType::Integer, Value::String(s)) => s
+            .trim()
+            .parse::<i64>()
+            .map(SqlValue::Integer)
+            .map_err(|_| "must be an integer".to_owned()),
+        (ArgType::Real, Value::Number(n)) => n
+            .as_f64()
+            .map(SqlValue::Real)
+            .ok_or_else(|| "must be a number".to_owned()),
+        (ArgType::Real, Value::String(s)) => match s.trim().parse::<f64>() {
+            Ok(f) if f.is_finite() => Ok(SqlValue::Real(f)),
+            _ => Err("must be a number".to_owned()),
+        },
+        (ArgType::Text, Value::String(s)) => Ok(SqlValue::Text(s.clone())),
+        (ArgType::Text, Value::Number(_) | Value::Bool(_)) => Ok(SqlValue::Text(val.to_string())),
+        (ArgType::Boolean, Value::Bool(b)) => Ok(SqlValue::Integer(*b as i64)),
+        (ArgType::Boolean, _) => match val {
+            Value::String(s) if s == "true" || s == "1" => Ok(SqlValue::Integer(1)),
+            Value::String(s) if s == "false" || s == "0" => Ok(SqlValue::Integer(0)),
+            Value::Number(n) if n.as_i64() == Some(1) => Ok(SqlValue::Integer(1)),
+            Value::Number(n) if n.as_i64() == Some(0) => Ok(SqlValue::Integer(0)),
+            _ => Err("must be a boolean".to_owned()),
+        },
+        (ArgType::Blob, Value::String(s)) => BASE64
+            .decode(s)
+            .map(SqlValue::Blob)
+            .map_err(|_| "must be base64".to_owned()),
+        (ArgType::Json, Value::String(s)) => serde_json::from_str::<Value>(s)
+            .map(|v| SqlValue::Text(v.to_string()))
+            .map_err(|_| "must be JSON".to_owned()),
+        (ArgType::Json, _) => Ok(SqlValue::Text(val.to_string())),
+        (ArgType::Date, Value::String(s)) if is_date(s) => Ok(SqlValue::Text(s.clone())),
+        (ArgType::Date, _) => Err("must be an ISO 8601 date".to_owned()),
+        (ArgType::Integer, _) => Err("must be an integer".to_owned()),
+        (ArgType::Real, _) => Err("must be a number".to_owned()),
+        (ArgType::Text, _) => Err("must be a string".to_owned()),
+        (ArgType::Blob, _) => Err("must be base64".to_owned()),
+    }
+}
+
+/// Whether a string is an ISO 8601 date or datetime that SQLite's date functions understand.
+fn is_date(s: &str) -> bool {
+    static DATE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(
+            r"^(\d{4})-(\d{2})-(\d{2})([T ](\d{2}):(\d{2})(:(\d{2})(\.\d+)?)?(Z|[+-]\d{2}:\d{2})?)?$",
+        )
+        .unwrap()
+    });
+    let Some(caps) = DATE.captures(s) else {
+        return false;
+    };
+    let num = |i: usize| -> u32 {
+        caps.get(i)
+            .and_then(|m| m.as_str().parse().ok())
+            .unwrap_or_default()
+    };
+    let (year, month, day) = (num(1), num(2), num(3));
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days_in_month).contains(&day) && num(5) < 24 && num(6) < 60 && num(8) < 60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    fn arg(decl: Value) -> Arg {
+        serde_json::from_value(decl).unwrap()
+    }
+
+    #[test]
+    /// values are coerced to their declared types
+    fn coercion() {
+        let a = arg(json!({ "name": "id", "type": "integer", "min": 1 }));
+        assert_eq!(a.coerce(&json!("42")), Ok(SqlValue::Integer(42)));
+        assert_eq!(a.coerce(&json!(42)), Ok(SqlValue::Integer(42)));
+        assert!(a.coerce(&json!("4.2")).is_err());
+        assert!(a.coerce(&json!(0)).is_err());
+        assert!(a.coerce(&json!(null)).is_err());
+        let a = arg(json!({ "name": "ok", "type": "boolean" }));
+        assert_eq!(a.coerce(&json!("true")), Ok(SqlValue::Integer(1)));
+        assert_eq!(a.coerce(&json!(false)), Ok(SqlValue::Integer(0)));
+        let a = arg(json!({ "name": "data", "type": "blob", "max_length": 2 }));
+        assert_eq!(
+            a.coerce(&json!("yv4=")),
+            Ok(SqlValue::Blob(vec![0xca, 0xfe]))
+        );
+        assert!(a.coerce(&json!("AAAA")).is_err());
+        let a = arg(json!({ "name": "doc", "type": "json" }));
+        assert_eq!(
+            a.coerce(&json!("[1, 2]")),
+            Ok(SqlValue::Text("[1,2]".to_owned()))
+        );
+        assert_eq!(
+            a.coerce(&json!({ "a": 1 })),
+            Ok(SqlValue::Text("{\"a\":1}".to_owned()))
+        );
+        let a = arg(json!({ "name": "day", "type": "date" }));
+        assert!(a.coerce(&json!("2024-02-29")).is_ok());
+        assert!(a.coerce(&json!("2024-07-04T12:00:00Z")).is_ok());
+        assert!(a.coerce(&json!("2023-02-29")).is_err());
+        assert!(a.coerce(&json!("yesterday")).is_err());
+    }
+
+    #[test]
+    /// values are checked against regex and enum constraints
+    fn constraints() {
+        let a =
+            arg(json!({ "name": "code", "type": "text", "regex": "^[a-z]+$", "max_length": 3 }));
+        assert!(a.coerce(&json!("abc")).is_ok());
+        assert!(a.coerce(&json!("ABC")).is_err());
+        assert!(a.coerce(&json!("abcd")).is_err());
+        let a = arg(json!({ "name": "n", "type": "integer", "enum": [1, 2] }));
+        assert!(a.coerce(&json!("2")).is_ok());
+        assert!(a.coerce(&json!("3")).is_err());
+    }
+
+    #[test]
+    /// bad declarations are rejected when the spec is loaded
+    fn bad_declarations() {
+        let bad = [
+            json!({ "name": "x", "regex": "(" }),
+            json!({ "name": "x", "type": "integer", "default": "abc" }),
+            json!({ "name": "x", "typ": "integer" }),
+        ];
+        for decl in bad {
+            assert!(serde_json::from_value::<Arg>(decl).is_err());
+        }
+    }
+}
@@ -0,0 +1,33 @@
+/// An error reading a spec.json.
+#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum SpecError {
+    File(std::io::Error),
+    JSON(serde_json::Error),
+    /// The spec.json has a mistake in it that deserializing it can't catch.
+    Invalid(String),
+}
+
+impl From<std::io::Error> for SpecError {
+    fn from(e: std::io::Error) -> Self {
+        SpecError::File(e)
+    }
+}
+
+impl From<serde_json::Error> for SpecError {
+    fn from(e: serde_json::Error) -> Self {
+        SpecError::JSON(e)
+    }
+}
+
+impl std::fmt::Display for SpecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpecError::File(e) => write!(f, "file error: {e}"),
+            SpecError::JSON(e) => write!(f, "JSON error: {e}"),
+            SpecError::Invalid(problem) => write!(f, "invalid spec: {problem}"),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
@@ -0,0 +1,16 @@
+//! The spec.json format of corolla servers: its queries, arg declarations, conversions and test cases.
+//! Kept apart from the server so that crates that only read specs, like `corolla-macros`, don't depend on it.
+mod args;
+mod error;
+mod spec;
+mod value;
+mod version;
+
+pub use args::{Arg, ArgType, Pattern};
+pub use error::SpecError;
+pub use spec::{
+    read_spec, BatchItem, BatchItemType, Conversion, Queries, ReadQuery, Spec, SpecTest, TestStep,
+    WriteQuery, WriteStatement,
+};
+pub use value::{BlobEncoding, ResultFormat, SqlValue};
+pub use version::{InstanceVersion, ParseVersionError, SpecVersion, Version};
@@ -0,0 +1,315 @@
+use super::{
+    args::Arg,
+    error::SpecError,
+    value::{BlobEncoding, ResultFormat},
+    version::{InstanceVersion, SpecVersion},
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs, path::Path};
+
+/// Represents a read-only database query (returns rows, does not change DB).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ReadQuery {
+    /// [A SQLite statement with parameters.](https://www.sqlite.org/c3ref/bind_blob.html) Only `?` parameters are tested.
+    pub sql_template: String,
+    /// The query's list of parameters, in order.
+    pub args: Vec<Arg>,
+    /// The columns the query results will use
+    pub cols: Vec<String>,
+    /// How result values are rendered. Can be overridden per request with the `x-corolla-format` header.
+    #[serde(default)]
+    pub format: ResultFormat,
+    /// How BLOB values in the results are encoded.
+    #[serde(default)]
+    pub blob_encoding: BlobEncoding,
+    /// Sets of arguments the query is run with in `--test` mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<HashMap<String, Value>>,
+}
+
+/// Represents a write-only database query (can return rows, changes DB).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WriteQuery {
+    /// [A SQLite statement with parameters.](https://www.sqlite.org/c3ref/bind_blob.html) Only `?` parameters are tested.
+    /// Left empty when the query has `statements` instead.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub sql_template: String,
+    /// The query's list of parameters, in order.
+    pub args: Vec<Arg>,
+    /// The columns rows returned by a `RETURNING` clause will use. Defaults to the statement's column names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cols: Option<Vec<String>>,
+    /// How BLOB values in returned rows are encoded.
+    #[serde(default)]
+    pub blob_encoding: BlobEncoding,
+    /// A list of statements to run in order, inside one transaction, instead of `sql_template`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub statements: Vec<WriteStatement>,
+    /// Sets of arguments the query is run with in `--test` mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<HashMap<String, Value>>,
+}
+
+/// One statement of a multi-statement write query.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WriteStatement {
+    /// Names the statement's results, so later statements can bind them as `<name>.rows_affected`, `<name>.last_insert_rowid`,
+    /// or `<name>.<column>` for a column of the first row returned by a `RETURNING` clause.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// [A SQLite statement with parameters.](https://www.sqlite.org/c3ref/bind_blob.html) Only `?` parameters are tested.
+    pub sql_template: String,
+    /// The names of the values bound to the statement's parameters, in order. Each is one of the query's args or an earlier statement's result.
+    pub args: Vec<String>,
+}
+
+/// Represents a DB conversion, which will be executed upon startup if the current DB version is <= Conversion.max.
+/// If the conversion is executed, the current DB version will become Conversion.new_version.
+#[derive(Serialize, Deserialize)]
+pub struct Conversion {
+    pub max_version: InstanceVersion,
+    pub new_version: InstanceVersion,
+    pub queries: Vec<String>,
+    /// Statements that undo the conversion, run by `corolla migrate --to` when rolling back past its new_version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub down_queries: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Queries {
+    pub read: HashMap<String, ReadQuery>,
+    pub write: HashMap<String, WriteQuery>,
+}
+
+/// Whether an item of a batch runs a read query or a write query.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemType {
+    Read,
+    Write,
+}
+
+/// One query of a batch.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchItem {
+    #[serde(rename = "type")]
+    pub r#type: BatchItemType,
+    /// The code name of the query in the query lookup table.
+    pub query: String,
+    /// Arguments to be bound to the query.
+    #[serde(default)]
+    pub args: HashMap<String, Value>,
+}
+
+/// A test case of a spec: queries run in order on a fresh database, with the outcomes they should have.
+#[derive(Serialize, Deserialize)]
+pub struct SpecTest {
+    pub name: String,
+    pub steps: Vec<TestStep>,
+}
+
+/// One query of a test case.
+#[derive(Serialize, Deserialize)]
+pub struct TestStep {
+    #[serde(flatten)]
+    pub call: BatchItem,
+    /// The rows the query should return, without the header row. For a write query, these are the rows returned by a `RETURNING` clause.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<Vec<Vec<Value>>>,
+    /// The error code the query should fail with, e.g. `constraint_violation`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The spec.json format, in Rust struct form.
+#[derive(Serialize, Deserialize)]
+pub struct Spec {
+    pub spec_version: SpecVersion,
+    pub version: InstanceVersion,
+    pub init: Vec<String>,
+    pub queries: Queries,
+    pub conversions: Vec<Conversion>,
+    /// Whether a column named differently in a query's `cols` than in its SQL fails the spec's checks, instead of only being warned about.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict_cols: bool,
+    /// Test cases run by `corolla test`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<SpecTest>,
+}
+
+impl WriteQuery {
+    /// Checks that the query has either a `sql_template` or `statements`, and that every statement arg refers to a query arg or an earlier statement's result.
+    fn validate(&self) -> Result<(), String> {
+        if self.statements.is_empty() == self.sql_template.is_empty() {
+            return Err("needs either a sql_template or a list of statements".to_owned());
+        }
+        let mut names: Vec<&str> = vec![];
+        for (i, statement) in self.statements.iter().enumerate() {
+            for arg in &statement.args {
+                let declared = self.args.iter().any(|a| &a.name == arg);
+                let earlier = arg
+                    .split_once('.')
+                    .is_some_and(|(name, _)| names.contains(&name));
+                if !declared && !earlier {
+                    return Err(format!("statement {i} binds unknown arg {arg}"));
+                }
+            }
+            if let Some(name) = &statement.name {
+                if names.contains(&name.as_str()) {
+                    return Err(format!("statement name {name} is used twice"));
+                }
+                names.push(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Conversion {
+    /// A SHA-256 hex digest of the conversion's queries, recorded when it's applied to tell if it has been edited since.
+    /// Its down_queries are left out, so they can be added after the conversion has been applied.
+    pub fn checksum(&self) -> String {
+        // hashing the JSON list keeps the boundaries between queries
+        let queries = serde_json::to_string(&self.queries).unwrap_or_default();
+        format!("{:x}", Sha256::digest(queries))
+    }
+}
+
+impl Spec {
+    /// Checks the spec for mistakes that deserializing it can't catch.
+    pub fn validate(&self) -> Result<(), SpecError> {
+        for (name, query) in &self.queries.write {
+            query
+                .validate()
+                .map_err(|e| SpecError::Invalid(format!("write query {name}: {e}")))?;
+        }
+        for test in &self.tests {
+            for (i, step) in test.steps.iter().enumerate() {
+                let (kind, exists) = match step.call.r#type {
+                    BatchItemType::Read => {
+                        ("read", self.queries.read.contains_key(&step.call.query))
+                    }
+                    BatchItemType::Write => {
+                        ("write", self.queries.write.contains_key(&step.call.query))
+                    }
+                };
+                if !exists {
+                    return Err(SpecError::Invalid(format!(
+                        "test {} step {i} runs unknown {kind} query {}",
+                        test.name, step.call.query
+                    )));
+                }
+                if step.rows.is_some() && step.error.is_some() {
+                    return Err(SpecError::Invalid(format!(
+                        "test {} step {i} expects both rows and an error",
+                        test.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a spec.json file into a `Spec` object.
+pub fn read_spec<P>(path: P) -> Result<Spec, SpecError>
+where
+    P: AsRef<Path>,
+{
+    info!("reading spec file");
+    let file = fs::File::open(path)?;
+    let spec: Spec = serde_json::from_reader(file)?;
+    spec.validate()?;
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::version::Version;
+    use pretty_assertions::assert_eq;
+    use std::{env, path::Path};
+
+    #[test]
+    /// versions convert to strings appropriately
+    fn read_and_parse_spec() {
+        let proj_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let spec_path = Path::new(&proj_dir).join("../examples/example_spec.json");
+        let spec = read_spec(spec_path).unwrap();
+        assert_eq!(spec.version, Version::from([1, 0, 1]));
+        assert_eq!(
+            spec.init.first().unwrap(),
+            "create table if not exists t (vacation_spot text);"
+        );
+        let read_query = spec.queries.read.get("read01").unwrap();
+        assert_eq!(read_query.sql_template, "select vacation_spot from t;");
+        assert_eq!(read_query.args.len(), 0);
+        assert_eq!(read_query.cols.first().unwrap(), "vacation_spot");
+        let proj_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+        let spec_path = Path::new(&proj_dir).join("../examples/example_spec_with_conversions.json");
+        let spec = read_spec(spec_path).unwrap();
+        assert_eq!(spec.version, Version::from([1, 0, 2]));
+        assert_eq!(
+            spec.init.first().unwrap(),
+            "create table if not exists t (vacation_spot text, notes text);"
+        );
+        let write_query = spec.queries.write.get("write01").unwrap();
+        assert_eq!(write_query.sql_template, "insert into t values (?,?);");
+        assert_eq!(write_query.args.len(), 2);
+        let conversion = spec.conversions.first().unwrap();
+        assert_eq!(conversion.max_version, "1.0.1".parse::<Version>().unwrap());
+        assert_eq!(conversion.new_version, "1.0.2".parse::<Version>().unwrap());
+    }
+
+    #[test]
+    /// versions can be written as lists or strings, and malformed ones are rejected when the spec is read
+    fn version_forms() {
+        let spec = |version: serde_json::Value| {
+            serde_json::from_value::<Spec>(serde_json::json!({
+                "spec_version": [1, 0, 0],
+                "version": version,
+                "init": [],
+                "queries": { "read": {}, "write": {} },
+                "conversions": [{ "max_version": "1.0", "new_version": [1, 1], "queries": [] }]
+            }))
+        };
+        let parsed = spec(serde_json::json!("1.1.0")).unwrap();
+        assert_eq!(parsed.version, Version::from([1, 1, 0]));
+        assert_eq!(parsed.conversions[0].max_version, Version::from([1, 0, 0]));
+        assert_eq!(
+            spec(serde_json::json!("1.x")).err().unwrap().to_string(),
+            "invalid version \"1.x\": expected numbers separated by dots, like 1.0.2"
+        );
+        assert!(spec(serde_json::json!([])).is_err());
+    }
+
+    #[test]
+    /// multi-statement write queries can only bind known args
+    fn validate_statements() {
+        let query = |statements: serde_json::Value| -> WriteQuery {
+            serde_json::from_value(serde_json::json!({
+                "args": ["name", "note"],
+                "statements": statements
+            }))
+            .unwrap()
+        };
+        let ok = query(serde_json::json!([
+            { "name": "person", "sql_template": "insert into people (name) values (?);", "args": ["name"] },
+            { "sql_template": "insert into notes values (?, ?);", "args": ["person.last_insert_rowid", "note"] }
+        ]));
+        assert!(ok.validate().is_ok());
+        let bad = query(serde_json::json!([
+            { "sql_template": "insert into notes values (?, ?);", "args": ["person.last_insert_rowid", "note"] },
+            { "name": "person", "sql_template": "insert into people (name) values (?);", "args": ["name"] }
+        ]));
+        assert_eq!(
+            bad.validate(),
+            Err("statement 0 binds unknown arg person.last_insert_rowid".to_owned())
+        );
+        let empty = query(serde_json::json!([]));
+        assert!(empty.validate().is_err());
+    }
+}
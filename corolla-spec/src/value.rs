@@ -0,0 +1,62 @@
+/// This file contains the result formats a spec can declare, and the SQLite values arguments are coerced to.
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// How the values in a query's result rows are rendered as JSON.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultFormat {
+    /// Values keep their SQLite type: INTEGER and REAL become numbers, TEXT becomes a string, NULL becomes `null`.
+    /// INTEGER values in a column declared `BOOLEAN` become `true`/`false`.
+    #[default]
+    Typed,
+    /// Every value is rendered as a string, and NULL becomes `""`. For clients written against older versions of Corolla.
+    Strings,
+}
+
+/// How BLOB values are encoded in query results.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlobEncoding {
+    #[default]
+    Base64,
+    Hex,
+}
+
+impl BlobEncoding {
+    /// Encodes a BLOB value as a string.
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            BlobEncoding::Base64 => BASE64.encode(bytes),
+            BlobEncoding::Hex => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+        }
+    }
+}
+
+/// A value that can be bound to a statement parameter, one variant per SQLite storage class.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Converts a JSON value as-is, for args without a declared type.
+/// `null` becomes NULL, numbers become INTEGER or REAL, booleans become 0 or 1, strings become TEXT, and arrays and objects become JSON text.
+impl From<&Value> for SqlValue {
+    fn from(val: &Value) -> Self {
+        match val {
+            Value::Null => SqlValue::Null,
+            Value::Bool(b) => SqlValue::Integer(*b as i64),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => SqlValue::Integer(i),
+                None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+            },
+            Value::String(s) => SqlValue::Text(s.clone()),
+            _ => SqlValue::Text(val.to_string()),
+        }
+    }
+}
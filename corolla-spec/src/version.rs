@@ -0,0 +1,238 @@
+/// This file contains methods for both spec versions and versions used by an instance of a Corolla DB.
+use std::{cmp::Ordering, fmt, ops::Deref, str::FromStr};
+
+use serde::{
+    de::{self, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+/// A general version type. Versions of different lengths compare as if the shorter one were padded with zeros, so `1.0` equals `1.0.0`.
+/// Uses [this Rust trick](https://stackoverflow.com/a/25415289).
+#[derive(Serialize, Clone, Debug)]
+pub struct Version(Vec<u64>);
+
+/// An error parsing a version that isn't numbers separated by dots, e.g. `1.x`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseVersionError {
+    version: String,
+}
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid version {:?}: expected numbers separated by dots, like 1.0.2",
+            self.version
+        )
+    }
+}
+
+impl std::error::Error for ParseVersionError {}
+
+impl Deref for Version {
+    type Target = Vec<u64>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Eq for Version {}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        for i in 0..len {
+            let a = self.0.get(i).unwrap_or(&0);
+            let b = other.0.get(i).unwrap_or(&0);
+            if a < b {
+                return Ordering::Less;
+            }
+            if a > b {
+                return Ordering::Greater;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl From<Version> for String {
+    fn from(value: Version) -> Self {
+        value
+            .0
+            .into_iter()
+            .map(|a| a.to_string())
+            .reduce(|a, b| format!("{a}.{b}"))
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .split('.')
+            .map(
+                |i| match !i.is_empty() && i.bytes().all(|b| b.is_ascii_digit()) {
+                    true => i.parse::<u64>().ok(),
+                    false => None,
+                },
+            )
+            .collect::<Option<Vec<u64>>>()
+            .map(Version)
+            .ok_or_else(|| ParseVersionError {
+                version: value.to_owned(),
+            })
+    }
+}
+
+/// Reads a version written either as a list of numbers, e.g. `[1, 0, 2]`, or as a string, e.g. `"1.0.2"`.
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VersionVisitor;
+
+        impl<'de> Visitor<'de> for VersionVisitor {
+            type Value = Version;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a version like [1, 0, 2] or \"1.0.2\"")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Version, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Version, A::Error> {
+                let mut parts = vec![];
+                while let Some(part) = seq.next_element::<u64>()? {
+                    parts.push(part);
+                }
+                match parts.is_empty() {
+                    true => Err(de::Error::invalid_length(0, &self)),
+                    false => Ok(Version(parts)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(VersionVisitor)
+    }
+}
+
+impl<const N: usize> From<[u64; N]> for Version {
+    fn from(value: [u64; N]) -> Self {
+        Version(value.to_vec())
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for i in self.iter() {
+            if first {
+                write!(f, "{i}")?;
+                first = false;
+            } else {
+                write!(f, ".{i}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The version of a spec.json schema. Will only be changed by an update to the Corolla codebase.
+pub type SpecVersion = Version;
+
+/// The version of a particular instance of a Corolla DB. Used to decide whether or not conversions in the instance's spec.json should be run.
+pub type InstanceVersion = Version;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[test]
+    /// versions convert to strings appropriately
+    fn version2str() {
+        let v = Version::from([1, 2, 3]);
+        assert_eq!(v.to_string(), "1.2.3".to_owned());
+        assert_eq!(format!("{v}"), "1.2.3".to_owned());
+        let v = Version::from([29, 000]);
+        assert_eq!(v.to_string(), "29.0".to_owned());
+    }
+
+    #[test]
+    /// strings convert to versions appropriately, and malformed ones are rejected
+    fn str2version() {
+        let v: Version = "1.2.3".parse().unwrap();
+        let w = Version::from([1, 2, 3]);
+        assert_eq!(v, w);
+        let v: Version = "10.20".parse().unwrap();
+        let w = Version::from([10, 20]);
+        assert_eq!(v, w);
+        for bad in [
+            "1.x",
+            "",
+            "1..2",
+            "1.2.",
+            "-1",
+            "+1",
+            "1.99999999999999999999",
+        ] {
+            assert_eq!(
+                bad.parse::<Version>(),
+                Err(ParseVersionError {
+                    version: bad.to_owned()
+                })
+            );
+        }
+        assert_eq!(
+            "1.x".parse::<Version>().unwrap_err().to_string(),
+            "invalid version \"1.x\": expected numbers separated by dots, like 1.0.2"
+        );
+    }
+
+    #[test]
+    /// versions are read from either lists of numbers or strings
+    fn deserialize_version() {
+        let v: Version = serde_json::from_str("[1, 0, 2]").unwrap();
+        assert_eq!(v, Version::from([1, 0, 2]));
+        let v: Version = serde_json::from_str("\"1.0.2\"").unwrap();
+        assert_eq!(v, Version::from([1, 0, 2]));
+        assert_eq!(serde_json::to_string(&v).unwrap(), "[1,0,2]");
+        for bad in ["\"1.x\"", "[]", "[1, -1]", "1", "{}"] {
+            assert!(serde_json::from_str::<Version>(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    /// version comparison, equality traits
+    fn version_type_has_cmp_traits() {
+        let v = Version::from([1, 2, 3]);
+        let w = Version::from([1, 2, 3]);
+        assert_eq!(v, w);
+        let w = Version::from([1, 2, 38]);
+        assert_ne!(v, w);
+        assert!(v <= w);
+        assert!(w > v);
+        // missing components count as zeros
+        let v = Version::from([1, 0]);
+        assert_eq!(v, Version::from([1, 0, 0]));
+        assert_ne!(v, Version::from([1, 0, 5]));
+        assert!(v < Version::from([1, 0, 5]));
+        assert!(Version::from([1, 1]) > Version::from([1, 0, 5]));
+        assert_eq!(v.cmp(&Version::from([1])), Ordering::Equal);
+    }
+}
@@ -0,0 +1,47 @@
+/// This file contains methods for checking a request's arguments against a query's declared args.
+use super::error::Error;
+use corolla_spec::{Arg, SqlValue};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Matches a request's arguments against a query's declared args, and returns the values to bind, in order.
+/// Optional args that were not supplied take their default value.
+pub fn resolve_args(
+    declared: &[Arg],
+    args: &HashMap<String, Value>,
+) -> Result<Vec<SqlValue>, Error> {
+    let missing: Vec<String> = declared
+        .iter()
+        .filter(|a| !a.optional && !args.contains_key(&a.name))
+        .map(|a| a.name.clone())
+        .collect();
+    let mut unexpected: Vec<String> = args
+        .keys()
+        .filter(|k| !declared.iter().any(|a| &a.name == *k))
+        .cloned()
+        .collect();
+    if !missing.is_empty() || !unexpected.is_empty() {
+        unexpected.sort();
+        return Err(Error::InvalidArgs {
+            expected: declared.iter().map(|a| a.name.clone()).collect(),
+            missing,
+            unexpected,
+        });
+    }
+    let mut vals = Vec::<SqlValue>::with_capacity(declared.len());
+    let mut problems = HashMap::<String, String>::new();
+    for arg in declared {
+        let val = args.get(&arg.name).unwrap_or(&arg.default);
+        match arg.coerce(val) {
+            Ok(val) => vals.push(val),
+            Err(problem) => {
+                problems.insert(arg.name.clone(), problem);
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(vals)
+    } else {
+        Err(Error::ArgValidation(problems))
+    }
+}
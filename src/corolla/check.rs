@@ -0,0 +1,373 @@
+/// This file contains checks of a spec's SQL against the database it will run on.
+use super::error::Error;
+use corolla_spec::{ReadQuery, WriteQuery};
+use libsqlite3_sys::{
+    sqlite3_bind_parameter_count, sqlite3_column_count, sqlite3_column_name, sqlite3_errmsg,
+    sqlite3_finalize, sqlite3_prepare_v2, sqlite3_stmt_readonly, SQLITE_OK,
+};
+use sqlx::SqliteConnection;
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_int, CStr},
+    ptr,
+};
+
+/// What SQLite reports about one prepared statement.
+#[derive(Debug, PartialEq)]
+pub struct StatementInfo {
+    /// Whether the statement leaves the database unchanged, according to
+    /// [`sqlite3_stmt_readonly`](https://www.sqlite.org/c3ref/stmt_readonly.html).
+    pub read_only: bool,
+    /// The number of values the statement binds.
+    pub params: usize,
+    /// The names of the columns the statement returns.
+    pub cols: Vec<String>,
+}
+
+/// What checking one query found.
+#[derive(Debug, Default, PartialEq)]
+pub struct Findings {
+    /// Mistakes that stop the spec from being served.
+    pub problems: Vec<String>,
+    /// Likely mistakes that are only logged, e.g. `cols` naming a column differently from the SQL.
+    pub warnings: Vec<String>,
+}
+
+impl Findings {
+    /// Counts the warnings as problems too, if `strict` is set.
+    pub fn strict(mut self, strict: bool) -> Self {
+        if strict {
+            self.problems.append(&mut self.warnings);
+        }
+        self
+    }
+}
+
+/// Prepares every statement in a SQL string, without running them.
+/// Fails with the SQLite error message if a statement doesn't prepare, e.g. because of a syntax error or a missing table.
+///
+/// Arguments:
+///
+/// * `conn` - The connection to prepare the statements on.
+/// * `sql` - One or more SQL statements.
+pub async fn prepare(conn: &mut SqliteConnection, sql: &str) -> Result<Vec<StatementInfo>, Error> {
+    let mut handle = conn.lock_handle().await?;
+    let db = handle.as_raw_handle().as_ptr();
+    let mut statements = vec![];
+    let mut rest = sql.as_bytes();
+    while !rest.is_empty() {
+        let mut stmt = ptr::null_mut();
+        let mut tail: *const c_char = ptr::null();
+        // SAFETY: the handle is locked, so sqlx makes no other calls on it, and `rest` outlives the statement.
+        // `tail` points into `rest`, just past the statement that was prepared.
+        unsafe {
+            let rc = sqlite3_prepare_v2(
+                db,
+                rest.as_ptr() as *const c_char,
+                rest.len() as c_int,
+                &mut stmt,
+                &mut tail,
+            );
+            if rc != SQLITE_OK {
+                let message = CStr::from_ptr(sqlite3_errmsg(db)).to_string_lossy();
+                return Err(Error::InvalidSpec(message.into_owned()));
+            }
+            rest = &rest[tail.offset_from(rest.as_ptr() as *const c_char) as usize..];
+            // whitespace and comments prepare to no statement at all
+            if stmt.is_null() {
+                continue;
+            }
+            let mut cols = vec![];
+            for i in 0..sqlite3_column_count(stmt) {
+                // SQLite returns NULL when it runs out of memory naming the column
+                let name = sqlite3_column_name(stmt, i);
+                if name.is_null() {
+                    sqlite3_finalize(stmt);
+                    return Err(Error::InvalidSpec(format!(
+                        "SQLite could not name column {i} of a statement"
+                    )));
+                }
+                cols.push(CStr::from_ptr(name).to_string_lossy().into_owned());
+            }
+            statements.push(StatementInfo {
+                read_only: sqlite3_stmt_readonly(stmt) != 0,
+                params: sqlite3_bind_parameter_count(stmt) as usize,
+                cols,
+            });
+            sqlite3_finalize(stmt);
+        }
+    }
+    Ok(statements)
+}
+
+/// Whether a SQLite error message reports SQL that doesn't parse, rather than e.g. a missing table.
+pub fn is_syntax_error(message: &str) -> bool {
+    message.ends_with("syntax error")
+        || message == "incomplete input"
+        || message.starts_with("unrecognized token")
+}
+
+/// Whether SQLite named a result column after a table column or an alias, rather than after the text of an expression like `count(*)`.
+fn is_plain_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Compares a query's `cols` with the columns its SQL returns. A different number of columns is a problem.
+/// A column named differently is only a warning, since `cols` can relabel columns for display, unless the spec sets `strict_cols`. Only columns with a plain name
+/// are compared by name, so an expression without an alias can be named anything in `cols`.
+fn check_cols(cols: &[String], returned: &[String], findings: &mut Findings) {
+    if cols.len() != returned.len() {
+        findings.problems.push(format!(
+            "cols lists {} columns but the SQL returns {} ({})",
+            cols.len(),
+            returned.len(),
+            returned.join(", ")
+        ));
+        return;
+    }
+    findings.warnings.extend(
+        cols.iter()
+            .zip(returned)
+            .enumerate()
+            .filter(|(_, (col, name))| is_plain_name(name) && !col.eq_ignore_ascii_case(name))
+            .map(|(i, (col, name))| format!("column {i} is {name} in the SQL but {col} in cols")),
+    );
+}
+
+/// Checks a read query against what SQLite reports about its statements, and returns everything found.
+pub fn check_read_query(query: &ReadQuery, statements: &[StatementInfo]) -> Findings {
+    let mut findings = Findings::default();
+    if statements.iter().any(|s| !s.read_only) {
+        findings.problems.push("is not read-only".to_owned());
+    }
+    let params: usize = statements.iter().map(|s| s.params).sum();
+    if params != query.args.len() {
+        findings.problems.push(format!(
+            "has {} args but its SQL has {params} parameters",
+            query.args.len()
+        ));
+    }
+    for statement in statements.iter().filter(|s| !s.cols.is_empty()) {
+        check_cols(&query.cols, &statement.cols, &mut findings);
+    }
+    if statements.iter().all(|s| s.cols.is_empty()) {
+        findings.problems.push("returns no columns".to_owned());
+    }
+    findings
+}
+
+/// Checks a write query by preparing its statements, and returns everything found.
+///
+/// Arguments:
+///
+/// * `conn` - The connection to prepare the statements on.
+/// * `query` - The query to check.
+pub async fn check_write_query(
+    conn: &mut SqliteConnection,
+    query: &WriteQuery,
+) -> Result<Findings, Error> {
+    // each statement with the number of values bound to it
+    let templates: Vec<(&str, usize)> = if query.statements.is_empty() {
+        vec![(&query.sql_template, query.args.len())]
+    } else {
+        query
+            .statements
+            .iter()
+            .map(|s| (s.sql_template.as_str(), s.args.len()))
+            .collect()
+    };
+    let mut findings = Findings::default();
+    let mut returned = vec![];
+    // the columns each named statement returns, which later statements can bind
+    let mut named: HashMap<&str, Vec<String>> = HashMap::new();
+    for (i, (sql, args)) in templates.into_iter().enumerate() {
+        let prefix = match query.statements.is_empty() {
+            true => String::new(),
+            false => format!("statement {i} "),
+        };
+        let statements = match prepare(conn, sql).await {
+            Ok(statements) => statements,
+            Err(Error::InvalidSpec(e)) => {
+                findings
+                    .problems
+                    .push(format!("{prefix}does not prepare: {e}"));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        let params: usize = statements.iter().map(|s| s.params).sum();
+        if params != args {
+            findings.problems.push(format!(
+                "{prefix}has {args} args but its SQL has {params} parameters"
+            ));
+        }
+        if let Some(statement) = query.statements.get(i) {
+            for arg in &statement.args {
+                if query.args.iter().any(|a| &a.name == arg) {
+                    continue;
+                }
+                let Some((name, field)) = arg.split_once('.') else {
+                    continue;
+                };
+                let Some(cols) = named.get(name) else {
+                    continue;
+                };
+                if !["rows_affected", "last_insert_rowid"].contains(&field)
+                    && !cols.iter().any(|c| c == field)
+                {
+                    findings.problems.push(match cols.is_empty() {
+                        true => {
+                            format!("{prefix}binds {arg}, but statement {name} returns no rows")
+                        }
+                        false => format!(
+                            "{prefix}binds {arg}, but statement {name} returns only {}",
+                            cols.join(", ")
+                        ),
+                    });
+                }
+            }
+        }
+        // the query's result rows come from the last statement that returns any
+        let cols = statements
+            .into_iter()
+            .rev()
+            .find(|s| !s.cols.is_empty())
+            .map(|s| s.cols)
+            .unwrap_or_default();
+        if let Some(name) = query.statements.get(i).and_then(|s| s.name.as_deref()) {
+            named.insert(name, cols.clone());
+        }
+        if !cols.is_empty() {
+            returned = cols;
+        }
+    }
+    if let Some(cols) = &query.cols {
+        check_cols(cols, &returned, &mut findings);
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use sqlx::Connection;
+
+    async fn test_conn() -> SqliteConnection {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("create table t (x text, y integer);")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        conn
+    }
+
+    #[tokio::test]
+    /// only statements that don't change the database are read-only
+    async fn read_only_statements() {
+        let mut conn = test_conn().await;
+        for (sql, read_only) in [
+            ("select x from t where x = ?;", true),
+            ("with z as (select x from t) select * from z; -- done", true),
+            ("delete from t;", false),
+            ("insert into t values ('a', 1) returning x;", false),
+            ("select x from t; delete from t;", false),
+        ] {
+            let statements = prepare(&mut conn, sql).await.unwrap();
+            assert_eq!(statements.iter().all(|s| s.read_only), read_only, "{sql}");
+        }
+        match prepare(&mut conn, "select z from t;").await {
+            Err(Error::InvalidSpec(message)) => assert_eq!(message, "no such column: z"),
+            _ => panic!("expected the statement to fail to prepare"),
+        }
+    }
+
+    #[tokio::test]
+    /// queries must bind as many values as their SQL has parameters, and name the columns it returns
+    async fn query_checks() {
+        let mut conn = test_conn().await;
+        let read = |sql: &str, args: serde_json::Value, cols: serde_json::Value| -> ReadQuery {
+            serde_json::from_value(json!({ "sql_template": sql, "args": args, "cols": cols }))
+                .unwrap()
+        };
+        let query = read(
+            "select x, count(*) from t where y = ?;",
+            json!(["y"]),
+            json!(["x", "n"]),
+        );
+        let statements = prepare(&mut conn, &query.sql_template).await.unwrap();
+        assert_eq!(check_read_query(&query, &statements), Findings::default());
+        let query = read(
+            "select y, x from t where y = ? and x = ?;",
+            json!(["y"]),
+            json!(["x", "y"]),
+        );
+        let statements = prepare(&mut conn, &query.sql_template).await.unwrap();
+        let findings = check_read_query(&query, &statements);
+        assert_eq!(
+            findings.problems,
+            vec!["has 1 args but its SQL has 2 parameters"]
+        );
+        // cols can relabel columns, so a different name is only a warning
+        assert_eq!(
+            findings.warnings,
+            vec![
+                "column 0 is y in the SQL but x in cols",
+                "column 1 is x in the SQL but y in cols"
+            ]
+        );
+        // unless the spec is strict about it
+        assert_eq!(
+            findings.strict(true).problems,
+            vec![
+                "has 1 args but its SQL has 2 parameters",
+                "column 0 is y in the SQL but x in cols",
+                "column 1 is x in the SQL but y in cols"
+            ]
+        );
+        let query = read("select x from t;", json!([]), json!(["x", "y"]));
+        let statements = prepare(&mut conn, &query.sql_template).await.unwrap();
+        assert_eq!(
+            check_read_query(&query, &statements).problems,
+            vec!["cols lists 2 columns but the SQL returns 1 (x)"]
+        );
+        let write: WriteQuery = serde_json::from_value(json!({
+            "args": ["x"],
+            "cols": ["y"],
+            "statements": [
+                { "sql_template": "insert into t values (?, ?);", "args": ["x"] },
+                { "sql_template": "insert into nope values (?);", "args": ["x"] },
+                { "sql_template": "update t set x = ? returning y;", "args": ["x"] }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            check_write_query(&mut conn, &write).await.unwrap().problems,
+            vec![
+                "statement 0 has 1 args but its SQL has 2 parameters",
+                "statement 1 does not prepare: no such table: nope"
+            ]
+        );
+        let write: WriteQuery = serde_json::from_value(json!({
+            "args": ["x"],
+            "statements": [
+                { "name": "a", "sql_template": "insert into t values (?, 1) returning x;", "args": ["x"] },
+                { "name": "b", "sql_template": "insert into t values (?, ?);", "args": ["a.x", "a.rows_affected"] },
+                { "sql_template": "update t set x = ? where y = ?;", "args": ["a.y", "b.x"] }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            check_write_query(&mut conn, &write).await.unwrap().problems,
+            vec![
+                "statement 2 binds a.y, but statement a returns only x",
+                "statement 2 binds b.x, but statement b returns no rows"
+            ]
+        );
+    }
+}
@@ -0,0 +1,398 @@
+/// This file contains methods for generating typed API clients from a spec.
+use super::error::Error;
+use corolla_spec::{Arg, ArgType, ResultFormat, Spec};
+use serde_json::Value;
+use std::{collections::HashMap, fmt::Write};
+
+/// The start of every generated TypeScript module: the types and client methods shared by every query.
+const TS_PRELUDE: &str = r#"// Generated by `corolla codegen ts` from a Corolla spec.json. Do not edit by hand.
+
+/** A value in a query result row. */
+export type Value = string | number | boolean | null;
+
+/** The result of a write query. */
+export interface WriteResult<Row = Record<string, Value>> {
+  rows_affected: number;
+  /** The rowid of the last row the query inserted, or `null` if it inserted no rows. */
+  last_insert_rowid: number | null;
+  /** The rows returned by a `RETURNING` clause, if any. */
+  rows?: Row[];
+}
+
+/** An error response from the Corolla server. */
+export class CorollaError extends Error {
+  public constructor(
+    public status: number,
+    public code: string,
+    message: string,
+    public details?: unknown,
+  ) {
+    super(message);
+  }
+}
+
+/** Turns result rows into objects keyed by the header row's column names. */
+function to_objects<Row>(rows: Value[][]): Row[] {
+  const [headers, ...rest] = rows;
+  return rest.map((row) =>
+    Object.fromEntries(
+      headers.map((key, i) => [key as string, row[i]]),
+    ) as Row
+  );
+}
+
+export class Corolla {
+  private url_base: string;
+  private server: string;
+
+  /**
+   * @param server The Corolla server, e.g. "http://localhost:50000". Empty parameter will default to the current origin.
+   * @param url_base The Corolla server's URL base, e.g. "/application". Trailing slashes will be trimmed.
+   */
+  public constructor(server: string = "", url_base: string = "") {
+    url_base = url_base.replace(/\/+$/, "");
+    if (url_base.length !== 0 && url_base.charAt(0) !== "/") {
+      url_base = "/" + url_base;
+    }
+    this.server = server;
+    this.url_base = url_base;
+  }
+
+  private async request(path: string, init?: RequestInit): Promise<any> {
+    const http_res = await fetch(`${this.server}${this.url_base}${path}`, init);
+    const body = await http_res.json();
+    if (!http_res.ok) {
+      throw new CorollaError(
+        http_res.status,
+        body.code,
+        body.message,
+        body.details,
+      );
+    }
+    return body;
+  }
+
+  private async read<Row>(
+    query: string,
+    args: { [key: string]: unknown },
+    json_args: string[] = [],
+  ): Promise<Row[]> {
+    const params = new URLSearchParams();
+    for (const [key, val] of Object.entries(args)) {
+      if (val !== undefined && val !== null) {
+        params.append(
+          key,
+          json_args.includes(key) ? JSON.stringify(val) : String(val),
+        );
+      }
+    }
+    const search = params.toString();
+    const rows: Value[][] = await this.request(
+      `/read/${encodeURIComponent(query)}${search.length === 0 ? "" : "?" + search}`,
+    );
+    return to_objects<Row>(rows);
+  }
+
+  private async write<Row>(
+    query: string,
+    args: { [key: string]: unknown },
+  ): Promise<WriteResult<Row>> {
+    const res = await this.request(`/write/${encodeURIComponent(query)}`, {
+      method: "POST",
+      headers: { "content-type": "application/json" },
+      body: JSON.stringify(args),
+    });
+    if (res.rows !== undefined) {
+      res.rows = to_objects<Row>(res.rows);
+    }
+    return res;
+  }
+"#;
+
+/// Turns a name from the spec into a valid TypeScript identifier. Different names can turn into the same identifier, e.g. `get-user` and `get_user`.
+fn ts_ident(name: &str) -> String {
+    name.chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// A TypeScript object key: a bare identifier if the name is one, otherwise a quoted string.
+fn ts_key(name: &str) -> String {
+    let ident = ts_ident(name);
+    match ident == name && !name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => ident,
+        false => Value::from(name).to_string(),
+    }
+}
+
+/// The TypeScript type of an argument's value.
+///
+/// Arguments:
+///
+/// * `arg` - The argument.
+/// * `read` - Whether the argument is sent in a read query's URL, where untyped values are sent as text.
+fn ts_arg_type(arg: &Arg, read: bool) -> String {
+    if let Some(allowed) = &arg.r#enum {
+        return allowed
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+    match arg.r#type {
+        Some(ArgType::Integer | ArgType::Real) => "number",
+        Some(ArgType::Text | ArgType::Blob | ArgType::Date) => "string",
+        Some(ArgType::Boolean) => "boolean",
+        Some(ArgType::Json) => "unknown",
+        None if read => "string | number | boolean",
+        None => "Value",
+    }
+    .to_owned()
+}
+
+/// A TypeScript object type with one property per argument.
+fn ts_args_type(args: &[Arg], read: bool) -> String {
+    let props: Vec<String> = args
+        .iter()
+        .map(|a| match a.optional {
+            true => format!("{}?: {} | null", ts_key(&a.name), ts_arg_type(a, read)),
+            false => format!("{}: {}", ts_key(&a.name), ts_arg_type(a, read)),
+        })
+        .collect();
+    match props.is_empty() {
+        true => "{}".to_owned(),
+        false => format!("{{ {} }}", props.join("; ")),
+    }
+}
+
+/// A TypeScript object type with one property per result column.
+fn ts_row_type(cols: &[String], format: ResultFormat) -> String {
+    let val = match format {
+        ResultFormat::Typed => "Value",
+        ResultFormat::Strings => "string",
+    };
+    let props: Vec<String> = cols
+        .iter()
+        .map(|c| format!("{}: {val}", ts_key(c)))
+        .collect();
+    format!("{{ {} }}", props.join("; "))
+}
+
+/// A JSDoc comment holding a query's SQL.
+fn ts_doc(sql: &str) -> String {
+    let sql = sql.replace("*/", "*\\/");
+    let lines: Vec<String> = sql.lines().map(|l| format!("   * {l}")).collect();
+    format!(
+        "  /**\n   * ```sql\n{}\n   * ```\n   */\n",
+        lines.join("\n")
+    )
+}
+
+/// Checks that no two query names turn into the same method name, and fails with the pair of names if they do.
+///
+/// Arguments:
+///
+/// * `kind` - `read` or `write`, which prefixes the method names.
+/// * `names` - The names of the queries of that kind, sorted.
+fn check_idents(kind: &str, names: &[&String]) -> Result<(), Error> {
+    let mut seen: HashMap<String, &String> = HashMap::new();
+    for name in names {
+        let ident = format!("{kind}_{}", ts_ident(name));
+        if let Some(other) = seen.insert(ident.clone(), name) {
+            return Err(Error::InvalidSpec(format!(
+                "{kind} queries {other} and {name} would both generate the TypeScript method {ident}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Generates a TypeScript module with a client class that has one typed method per query:
+/// `read_<name>` for read queries and `write_<name>` for write queries.
+/// Read results are returned as objects keyed by the query's `cols`.
+/// Fails if two query names of the same kind would generate the same method name.
+///
+/// Arguments:
+///
+/// * `spec` - The spec whose queries the client calls.
+pub fn typescript(spec: &Spec) -> Result<String, Error> {
+    let mut types = String::new();
+    let mut methods = String::new();
+    let mut names: Vec<&String> = spec.queries.read.keys().collect();
+    names.sort();
+    check_idents("read", &names)?;
+    for name in names {
+        let query = &spec.queries.read[name];
+        let ident = format!("read_{}", ts_ident(name));
+        // writing to a String can't fail
+        let _ = writeln!(
+            types,
+            "export type {ident}_args = {};",
+            ts_args_type(&query.args, true)
+        );
+        let _ = writeln!(
+            types,
+            "export type {ident}_row = {};",
+            ts_row_type(&query.cols, query.format)
+        );
+        methods.push('\n');
+        methods.push_str(&ts_doc(&query.sql_template));
+        let default = match query.args.iter().all(|a| a.optional) {
+            true => " = {}",
+            false => "",
+        };
+        // JSON args are sent as JSON text, since the URL can only hold strings
+        let json_args: Vec<&str> = query
+            .args
+            .iter()
+            .filter(|a| a.r#type == Some(ArgType::Json))
+            .map(|a| a.name.as_str())
+            .collect();
+        let json_args = match json_args.is_empty() {
+            true => String::new(),
+            false => format!(", {}", Value::from(json_args)),
+        };
+        let _ = writeln!(
+            methods,
+            "  public {ident}(args: {ident}_args{default}): Promise<{ident}_row[]> {{\n    return this.read<{ident}_row>({}, args{json_args});\n  }}",
+            Value::from(name.as_str())
+        );
+    }
+    let mut names: Vec<&String> = spec.queries.write.keys().collect();
+    names.sort();
+    check_idents("write", &names)?;
+    for name in names {
+        let query = &spec.queries.write[name];
+        let ident = format!("write_{}", ts_ident(name));
+        let _ = writeln!(
+            types,
+            "export type {ident}_args = {};",
+            ts_args_type(&query.args, false)
+        );
+        let row = match &query.cols {
+            Some(cols) => {
+                let _ = writeln!(
+                    types,
+                    "export type {ident}_row = {};",
+                    ts_row_type(cols, ResultFormat::Typed)
+                );
+                format!("{ident}_row")
+            }
+            None => "Record<string, Value>".to_owned(),
+        };
+        methods.push('\n');
+        let sql = match query.statements.is_empty() {
+            true => query.sql_template.clone(),
+            false => query
+                .statements
+                .iter()
+                .map(|s| s.sql_template.as_str())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+        methods.push_str(&ts_doc(&sql));
+        let _ = writeln!(
+            methods,
+            "  public {ident}(args: {ident}_args): Promise<WriteResult<{row}>> {{\n    return this.write<{row}>({}, args);\n  }}",
+            Value::from(name.as_str())
+        );
+    }
+    Ok(format!("{TS_PRELUDE}{methods}}}\n\n{types}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    /// each query gets a method typed by its args and cols
+    fn typescript_client() {
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": [],
+            "queries": {
+                "read": {
+                    "people": {
+                        "sql_template": "select name, \"favorite color\" from people where age >= ? and json_valid(?);",
+                        "args": [
+                            { "name": "min_age", "type": "integer", "optional": true },
+                            { "name": "filter", "type": "json", "optional": true }
+                        ],
+                        "cols": ["name", "favorite color"]
+                    }
+                },
+                "write": {
+                    "add-person": {
+                        "sql_template": "insert into people values (?, ?) returning id;",
+                        "args": ["name", { "name": "color", "enum": ["red", "blue"] }],
+                        "cols": ["id"]
+                    }
+                }
+            },
+            "conversions": []
+        }))
+        .unwrap();
+        let ts = typescript(&spec).unwrap();
+        assert!(ts.starts_with(TS_PRELUDE));
+        let generated: Vec<&str> = ts[TS_PRELUDE.len()..].lines().collect();
+        assert_eq!(
+            generated,
+            vec![
+                "",
+                "  /**",
+                "   * ```sql",
+                "   * select name, \"favorite color\" from people where age >= ? and json_valid(?);",
+                "   * ```",
+                "   */",
+                "  public read_people(args: read_people_args = {}): Promise<read_people_row[]> {",
+                "    return this.read<read_people_row>(\"people\", args, [\"filter\"]);",
+                "  }",
+                "",
+                "  /**",
+                "   * ```sql",
+                "   * insert into people values (?, ?) returning id;",
+                "   * ```",
+                "   */",
+                "  public write_add_person(args: write_add_person_args): Promise<WriteResult<write_add_person_row>> {",
+                "    return this.write<write_add_person_row>(\"add-person\", args);",
+                "  }",
+                "}",
+                "",
+                "export type read_people_args = { min_age?: number | null; filter?: unknown | null };",
+                "export type read_people_row = { name: Value; \"favorite color\": Value };",
+                "export type write_add_person_args = { name: Value; color: \"red\" | \"blue\" };",
+                "export type write_add_person_row = { id: Value };",
+            ]
+        );
+    }
+
+    #[test]
+    /// query names that turn into the same method name are refused
+    fn typescript_collisions() {
+        let query = json!({ "sql_template": "select 1;", "args": [], "cols": ["one"] });
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": [],
+            "queries": {
+                "read": { "get-user": query, "get_user": query },
+                "write": {}
+            },
+            "conversions": []
+        }))
+        .unwrap();
+        match typescript(&spec) {
+            Err(Error::InvalidSpec(e)) => assert_eq!(
+                e,
+                "read queries get-user and get_user would both generate the TypeScript method read_get_user"
+            ),
+            _ => panic!("expected the names to collide"),
+        }
+    }
+}
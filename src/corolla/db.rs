@@ -1,58 +1,620 @@
 use super::{
+    args::resolve_args,
+    check::{check_read_query, check_write_query, is_syntax_error, prepare, Findings},
     error::Error,
-    spec::{Queries, Spec},
-    version::{InstanceVersion, Version},
+    plan::{plan_conversions, plan_rollback},
+    value::{bind, row_to_json},
 };
-use log::{debug, info};
+use corolla_spec::{
+    BatchItem, BatchItemType, BlobEncoding, InstanceVersion, Queries, ResultFormat, Spec, SqlValue,
+    Version,
+};
+use libsqlite3_sys::sqlite3_set_last_insert_rowid;
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::Value;
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqliteJournalMode},
-    Pool, Row, Sqlite, SqlitePool,
+    pool::PoolConnection,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions},
+    Column, ConnectOptions, Connection, Executor, Pool, Row, Sqlite, SqliteConnection, Statement,
+};
+use std::{
+    collections::HashMap,
+    path::Path,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
 };
-use std::{collections::HashMap, ops::Deref, sync::Arc};
-use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::Mutex;
+
+/// The outcome of a write query.
+#[derive(Serialize, Debug)]
+pub struct WriteResult {
+    /// The number of rows the query inserted, updated or deleted.
+    pub rows_affected: u64,
+    /// The rowid of the last row the query inserted, or `None` if it inserted no rows.
+    pub last_insert_rowid: Option<i64>,
+    /// The rows returned by a `RETURNING` clause, in the same shape as read query results.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<Vec<Vec<Value>>>,
+}
+
+/// Runs a write statement on a connection. If the statement has a `RETURNING` clause, the rows it returns are collected too.
+///
+/// Arguments:
+///
+/// * `conn` - The connection to run the statement on. The caller must hold the writer connection.
+/// * `sql` - SQL statement to execute.
+/// * `vals` - Values to be bound to the statement, in order.
+/// * `cols` - The header row for returned rows. Defaults to the statement's column names.
+/// * `format` - How returned values are rendered.
+/// * `blob_encoding` - How returned BLOB values are encoded.
+async fn execute_write(
+    conn: &mut SqliteConnection,
+    sql: &str,
+    vals: Vec<SqlValue>,
+    cols: Option<&Vec<String>>,
+    format: ResultFormat,
+    blob_encoding: BlobEncoding,
+) -> Result<WriteResult, Error> {
+    reset_last_insert_rowid(conn).await?;
+    let prepared = conn.prepare(sql).await?;
+    let mut statement = prepared.query();
+    for val in vals {
+        statement = bind(val, statement);
+    }
+    if prepared.columns().is_empty() {
+        let res = statement.execute(&mut *conn).await?;
+        return Ok(WriteResult {
+            rows_affected: res.rows_affected(),
+            last_insert_rowid: Some(res.last_insert_rowid()).filter(|id| *id != 0),
+            rows: None,
+        });
+    }
+    // changes() isn't reset by a statement that changes nothing, like a select, so only trust it if the total moved
+    let total_changes_before: i64 = sqlx::query_scalar("select total_changes();")
+        .fetch_one(&mut *conn)
+        .await?;
+    let sql_res = statement.fetch_all(&mut *conn).await?;
+    let mut rows = Vec::<Vec<Value>>::with_capacity(sql_res.len() + 1);
+    rows.push(match cols {
+        Some(cols) => cols.iter().map(|c| Value::from(c.as_str())).collect(),
+        None => prepared
+            .columns()
+            .iter()
+            .map(|c| Value::from(c.name()))
+            .collect(),
+    });
+    for row in sql_res {
+        rows.push(row_to_json(&row, format, blob_encoding)?);
+    }
+    // the statement's own result was consumed by fetching its rows
+    let (changes, total_changes, last_insert_rowid): (i64, i64, i64) =
+        sqlx::query_as("select changes(), total_changes(), last_insert_rowid();")
+            .fetch_one(&mut *conn)
+            .await?;
+    let rows_affected = match total_changes == total_changes_before {
+        true => 0,
+        false => changes as u64,
+    };
+    Ok(WriteResult {
+        rows_affected,
+        last_insert_rowid: Some(last_insert_rowid).filter(|id| *id != 0),
+        rows: Some(rows),
+    })
+}
+
+/// Sets the connection's last insert rowid to 0, so a statement that inserts no rows leaves it at 0
+/// instead of reporting the rowid of an earlier insert.
+async fn reset_last_insert_rowid(conn: &mut SqliteConnection) -> Result<(), Error> {
+    let mut handle = conn.lock_handle().await?;
+    // SAFETY: the handle is locked, so sqlx makes no other calls on it.
+    unsafe { sqlite3_set_last_insert_rowid(handle.as_raw_handle().as_ptr(), 0) };
+    Ok(())
+}
+
+/// The result of one query of a batch.
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum BatchResult {
+    Read(Vec<Vec<Value>>),
+    Write(WriteResult),
+}
 
 /// Represents a connection to a SQLite database.
+///
+/// All writes go through one connection, one at a time. Reads use a pool of read-only connections, and
+/// since the database is in WAL mode they see the last committed state without waiting for a write in progress.
 #[derive(Clone)]
 pub struct DB {
-    /// A read/write lock on top of a SQLite connection pool.
-    conn: Arc<RwLock<Pool<Sqlite>>>,
-    /// A lookup table of DB queries.
-    queries: Queries,
+    /// The only connection that writes to the database, behind a lock that serializes writes.
+    writer: Arc<Mutex<SqliteConnection>>,
+    /// A pool of read-only connections.
+    readers: Pool<Sqlite>,
+    /// The queries being served, swapped as a whole when the spec is reloaded.
+    served: Arc<RwLock<Arc<Served>>>,
+    /// Whether a spec older than the database may downgrade its recorded version, on startup and on reloads.
+    allow_downgrade: bool,
+}
+
+/// How to open a database with `DB::from_spec`, built up from the defaults:
+///
+/// ```
+/// # use std::time::Duration;
+/// let options = corolla::DbOptions::default()
+///     .busy_timeout(Duration::from_secs(10))
+///     .allow_downgrade(true);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct DbOptions {
+    pub(crate) busy_timeout: Duration,
+    pub(crate) allow_downgrade: bool,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            busy_timeout: Duration::from_secs(5),
+            allow_downgrade: false,
+        }
+    }
+}
+
+impl DbOptions {
+    /// How long a connection waits for a lock held by another process before failing with `SQLITE_BUSY`. Defaults to 5 seconds.
+    pub fn busy_timeout(mut self, busy_timeout: Duration) -> Self {
+        self.busy_timeout = busy_timeout;
+        self
+    }
+
+    /// Whether to open a database that a newer spec has converted, and record the older spec's version in it. Defaults to false.
+    pub fn allow_downgrade(mut self, allow_downgrade: bool) -> Self {
+        self.allow_downgrade = allow_downgrade;
+        self
+    }
+}
+
+/// The queries a DB serves, and the version of the spec they come from.
+pub struct Served {
+    pub version: InstanceVersion,
+    pub queries: Queries,
 }
 
 impl DB {
-    /// Construct a new DB object, which consists of a pooled SQLite connection wrapped by a read/write lock and a query lookup.
+    /// Construct a new DB object, which consists of a writer connection, a pool of reader connections and a query lookup.
     ///
     /// Arguments:
     ///
     /// * `db` - Filepath to the SQLite database.
     /// * `spec` - Filepath to the spec.json
-    pub async fn from_spec(db: &str, spec: &Spec) -> Result<Self, Error> {
+    /// * `options` - How to open the database.
+    pub async fn from_spec(db: &str, spec: &Spec, options: DbOptions) -> Result<Self, Error> {
         info!("opening sqlite db {db}");
-        let conn = SqlitePool::connect_with(
-            SqliteConnectOptions::new()
-                .create_if_missing(true)
-                .filename(db)
-                .journal_mode(SqliteJournalMode::Wal),
+        let connect_options = SqliteConnectOptions::new()
+            .filename(db)
+            .busy_timeout(options.busy_timeout);
+        let db = Self::open(connect_options, spec, options.allow_downgrade).await?;
+        info!("checking queries against the DB");
+        let problems = Self::_check_queries(
+            &mut *db.readers.acquire().await?,
+            &spec.queries,
+            spec.strict_cols,
         )
         .await?;
+        if !problems.is_empty() {
+            return Err(Error::QueryChecks(problems));
+        }
+        Ok(db)
+    }
+    /// Construct a new DB object on a throwaway in-memory database, which is dropped along with the DB object.
+    /// The database is built the way a new live one is: `init` runs, and the conversions don't.
+    /// Its queries are not checked; see `self_check`.
+    ///
+    /// Arguments:
+    ///
+    /// * `spec` - The spec to build the database from.
+    pub(crate) async fn in_memory(spec: &Spec) -> Result<Self, Error> {
+        // every in-memory database needs its own name, so that its connections share it with nothing else
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "file:corolla-{}-{}",
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        info!("opening in-memory sqlite db {name}");
+        let options = SqliteConnectOptions::new()
+            .filename(name)
+            .in_memory(true)
+            .shared_cache(true);
+        Self::open(options, spec, false).await
+    }
+    /// Checks a spec's conversions on a throwaway in-memory database of their own, and returns a report of every problem found.
+    /// Every conversion query is checked for syntax errors, and then the conversion is replayed in its own transaction, so that later
+    /// conversions see what earlier ones did. Conversions are written against older versions of the schema, which an empty database
+    /// need not have, so a conversion that fails to replay is only logged.
+    ///
+    /// Arguments:
+    ///
+    /// * `spec` - The spec whose conversions to check.
+    async fn _check_conversions(spec: &Spec) -> Result<Vec<String>, Error> {
+        let mut conn = SqliteConnectOptions::new()
+            .in_memory(true)
+            .connect()
+            .await?;
+        let mut problems = vec![];
+        for (i, conversion) in spec.conversions.iter().enumerate() {
+            let found = problems.len();
+            for (j, query) in conversion.queries.iter().enumerate() {
+                match prepare(&mut conn, query).await {
+                    Err(Error::InvalidSpec(e)) if is_syntax_error(&e) => {
+                        problems.push(format!("conversion {i} query {j} does not parse: {e}"))
+                    }
+                    Ok(_) | Err(Error::InvalidSpec(_)) => (),
+                    Err(e) => return Err(e),
+                }
+            }
+            if problems.len() > found {
+                continue;
+            }
+            info!("replaying conversion {i}");
+            let mut tx = conn.begin().await?;
+            let mut failed = None;
+            for query in &conversion.queries {
+                if let Err(e) = Self::_write_raw_query(&mut tx, query).await {
+                    failed = Some(e);
+                    break;
+                }
+            }
+            match failed {
+                None => tx.commit().await?,
+                // dropping the transaction rolls the conversion back
+                Some(e) => warn!("conversion {i} does not replay on an empty database: {e}"),
+            }
+        }
+        Ok(problems)
+    }
+    /// Connects to a database, brings it up to date with the spec, and wraps it in a DB object.
+    async fn open(
+        options: SqliteConnectOptions,
+        spec: &Spec,
+        allow_downgrade: bool,
+    ) -> Result<Self, Error> {
+        // the writer creates the database and switches it to WAL mode, so it must connect before the readers
+        let writer = options
+            .clone()
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .connect()
+            .await?;
+        // connections to a shared in-memory database share its write access, so readers also set query_only
+        let readers = SqlitePoolOptions::new()
+            .connect_with(options.read_only(true).pragma("query_only", "ON"))
+            .await?;
         debug!("initializing DB object");
-        let conn = Arc::new(RwLock::new(conn));
-        let queries = spec.queries.clone();
-        let db = DB { conn, queries };
-        info!("checking if corolla DB has been initialized");
+        let db = DB {
+            writer: Arc::new(Mutex::new(writer)),
+            readers,
+            served: Arc::new(RwLock::new(Arc::new(Served {
+                version: spec.version.clone(),
+                queries: spec.queries.clone(),
+            }))),
+            allow_downgrade,
+        };
+        {
+            let mut conn = db.writer.lock().await;
+            let version = Self::_instance_version(&mut conn).await?;
+            Self::_migrate(&mut conn, spec, version, allow_downgrade).await?;
+        }
+        Ok(db)
+    }
+    /// Brings a database up to date with a spec: runs its conversions, then its init statements, then records its version.
+    ///
+    /// Arguments:
+    ///
+    /// * `conn` - The writer connection, or a transaction on it.
+    /// * `spec` - The spec to bring the database up to date with.
+    /// * `version` - The database's current instance version, or `None` if corolla has never initialized it.
+    /// * `allow_downgrade` - Whether to go ahead if the database is at a newer version than the spec, and record the spec's version in it.
+    async fn _migrate(
+        conn: &mut SqliteConnection,
+        spec: &Spec,
+        version: Option<InstanceVersion>,
+        allow_downgrade: bool,
+    ) -> Result<(), Error> {
+        if let Some(v) = version.as_ref().filter(|v| **v > spec.version) {
+            match allow_downgrade {
+                true => warn!(
+                    "the database is at version {v}, newer than the spec's version {}; downgrading it",
+                    spec.version
+                ),
+                false => {
+                    return Err(Error::DatabaseNewerThanSpec {
+                        database: v.clone(),
+                        spec: spec.version.clone(),
+                    })
+                }
+            }
+        }
+        // a downgraded database keeps the ledger rows of conversions that only the newer spec has
+        let downgrading = version.as_ref().is_some_and(|v| *v > spec.version);
+        Self::_init_migration_ledger(conn).await?;
+        Self::_check_applied_conversions(conn, spec, downgrading).await?;
         // if DB is initialized (can find a corolla instance version inside it), then run the conversions
-        match db._instance_version().await? {
-            Some(v) => db._run_conversions(spec, v).await?,
+        match version {
+            Some(v) => Self::_run_conversions(conn, spec, v).await?,
             _ => info!("DB is not initialized yet"),
         };
         info!("running init statements from spec");
         for s in &spec.init {
-            db._write_raw_query(&s, None).await?;
+            Self::_write_raw_query(conn, s).await?;
         }
         info!("initializing corolla DB tables");
-        let _ = db._init_corolla_tables(&spec).await?;
-        Ok(db)
+        Self::_init_corolla_tables(conn, spec).await?;
+        Ok(())
+    }
+    /// Switches to a new spec without restarting: brings the database up to date with it, checks its queries, and then swaps them in for the queries being served.
+    /// Runs in one transaction on the writer, so writes wait for the reload to finish. If the spec's queries fail their checks,
+    /// or a conversion or init statement fails, the transaction is rolled back and the old queries stay in place.
+    ///
+    /// Arguments:
+    ///
+    /// * `spec` - The new spec.
+    pub async fn reload(&self, spec: &Spec) -> Result<(), Error> {
+        debug!("waiting for the writer connection");
+        let mut conn = self.writer.lock().await;
+        let version = Self::_instance_version(&mut conn).await?;
+        let mut tx = conn.begin().await?;
+        Self::_migrate(&mut tx, spec, version, self.allow_downgrade).await?;
+        info!("checking queries against the DB");
+        let problems = Self::_check_queries(&mut tx, &spec.queries, spec.strict_cols).await?;
+        if !problems.is_empty() {
+            return Err(Error::QueryChecks(problems));
+        }
+        tx.commit().await?;
+        *self.served.write().unwrap() = Arc::new(Served {
+            version: spec.version.clone(),
+            queries: spec.queries.clone(),
+        });
+        Ok(())
+    }
+    /// Closes the database cleanly: waits for the write in progress, if any, closes the readers,
+    /// and then checkpoints the WAL into the database file and truncates it. The DB can't serve reads after this.
+    pub async fn close(&self) -> Result<(), Error> {
+        debug!("waiting for the writer connection");
+        let mut conn = self.writer.lock().await;
+        // open readers would keep the checkpoint from truncating the WAL
+        self.readers.close().await;
+        info!("checkpointing the WAL");
+        sqlx::query("pragma wal_checkpoint(TRUNCATE);")
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+    /// Reads a database's instance version without changing the database.
+    /// Returns `None` if the file doesn't exist or corolla has never initialized it.
+    ///
+    /// Arguments:
+    ///
+    /// * `db` - Filepath to the SQLite database.
+    /// * `busy_timeout` - How long to wait for a lock held by another process.
+    pub async fn read_version(
+        db: &str,
+        busy_timeout: Duration,
+    ) -> Result<Option<InstanceVersion>, Error> {
+        if !Path::new(db).exists() {
+            return Ok(None);
+        }
+        let mut conn = SqliteConnectOptions::new()
+            .filename(db)
+            .read_only(true)
+            .busy_timeout(busy_timeout)
+            .connect()
+            .await?;
+        let version = Self::_instance_version(&mut conn).await;
+        conn.close().await?;
+        version
+    }
+    /// Brings a temporary copy of a database up to date with a spec and checks the spec's queries against it,
+    /// or rolls the copy back to a previous version, and returns the copy's resulting schema, one `create` statement per table, index, view or trigger.
+    /// The database itself isn't changed, and the copy is deleted afterwards.
+    ///
+    /// Arguments:
+    ///
+    /// * `db` - Filepath to the SQLite database. If it doesn't exist, the spec is applied to a new database.
+    /// * `spec` - The spec to apply.
+    /// * `to` - The version to roll the copy back to, instead of bringing it up to date.
+    /// * `options` - How to open the database and its copy.
+    pub async fn dry_run(
+        db: &str,
+        spec: &Spec,
+        to: Option<&InstanceVersion>,
+        options: DbOptions,
+    ) -> Result<Vec<String>, Error> {
+        // only this process can get at the copy, and it's deleted along with the directory
+        let dir = tempfile::Builder::new()
+            .prefix("corolla-dry-run-")
+            .tempdir()?;
+        let copy = dir
+            .path()
+            .join("copy.sqlite3")
+            .to_string_lossy()
+            .into_owned();
+        if Path::new(db).exists() {
+            info!("copying {db} to {copy}");
+            let mut conn = SqliteConnectOptions::new()
+                .filename(db)
+                .read_only(true)
+                .busy_timeout(options.busy_timeout)
+                .connect()
+                .await?;
+            // unlike copying the file, this also picks up changes still in the WAL
+            sqlx::query("vacuum into ?;")
+                .bind(&copy)
+                .execute(&mut conn)
+                .await?;
+            conn.close().await?;
+        }
+        match to {
+            Some(target) => {
+                Self::roll_back(&copy, spec, target, options.busy_timeout).await?;
+            }
+            None => DB::from_spec(&copy, spec, options).await?.close().await?,
+        }
+        let mut conn = SqliteConnectOptions::new()
+            .filename(&copy)
+            .read_only(true)
+            .connect()
+            .await?;
+        let schema = sqlx::query_scalar(
+            "select sql from sqlite_schema where sql is not null and name not like 'sqlite_%' order by rowid;",
+        )
+        .fetch_all(&mut conn)
+        .await?;
+        conn.close().await?;
+        Ok(schema)
+    }
+    /// Rolls a database back to a previous version: runs the `down_queries` of every conversion applied past that version, newest first,
+    /// each in its own transaction, the last of which also sets the database to the target version, and returns that version.
+    /// Refuses to change anything if a conversion on the way back has no `down_queries`, undoing the conversions would go past the target,
+    /// or applied conversions have changed since.
+    /// The spec's init statements aren't run, and its queries aren't checked.
+    ///
+    /// Arguments:
+    ///
+    /// * `db` - Filepath to the SQLite database.
+    /// * `spec` - The spec holding the conversions applied to the database.
+    /// * `target` - The version to roll back to.
+    /// * `busy_timeout` - How long to wait for a lock held by another process.
+    pub async fn roll_back(
+        db: &str,
+        spec: &Spec,
+        target: &InstanceVersion,
+        busy_timeout: Duration,
+    ) -> Result<InstanceVersion, Error> {
+        if !Path::new(db).exists() {
+            return Err(Error::RollbackRefused(format!("{db} does not exist")));
+        }
+        let mut conn = SqliteConnectOptions::new()
+            .filename(db)
+            .busy_timeout(busy_timeout)
+            .connect()
+            .await?;
+        let res = Self::_roll_back(&mut conn, spec, target).await;
+        conn.close().await?;
+        res
+    }
+    /// Rolls the database on a connection back to a previous version. See `roll_back`.
+    async fn _roll_back(
+        conn: &mut SqliteConnection,
+        spec: &Spec,
+        target: &InstanceVersion,
+    ) -> Result<InstanceVersion, Error> {
+        let version = match Self::_instance_version(conn).await? {
+            Some(v) => v,
+            None => {
+                return Err(Error::RollbackRefused(
+                    "the database is not initialized".to_owned(),
+                ))
+            }
+        };
+        if *target > version {
+            return Err(Error::RollbackRefused(format!(
+                "the database is at version {version}, below {target}"
+            )));
+        }
+        Self::_init_migration_ledger(conn).await?;
+        Self::_check_applied_conversions(conn, spec, false).await?;
+        let (steps, end) = plan_rollback(spec, &version, target);
+        let missing: Vec<String> = steps
+            .iter()
+            .filter(|step| spec.conversions[step.index].down_queries.is_none())
+            .map(|step| step.index.to_string())
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::RollbackRefused(format!(
+                "conversions on the way back to {target} have no down_queries: {}",
+                missing.join(", ")
+            )));
+        }
+        if end != *target {
+            return Err(Error::RollbackRefused(format!(
+                "undoing conversions goes past {target}, to {end}"
+            )));
+        }
+        if steps.is_empty() {
+            // nothing to undo, so only the version changes
+            Self::_write_instance_version(conn, target).await?;
+        }
+        let count = steps.len();
+        for (n, step) in steps.into_iter().enumerate() {
+            let i = step.index;
+            info!("undoing conversion {i}");
+            let mut tx = conn.begin().await?;
+            for query in spec.conversions[i].down_queries.iter().flatten() {
+                Self::_write_raw_query(&mut tx, query).await?;
+            }
+            sqlx::query("delete from corolla_migrations where conversion = ?;")
+                .bind(i as i64)
+                .execute(&mut *tx)
+                .await?;
+            // the last conversion undone leaves the database at the target, which can be past its max_version
+            let to_version = match n + 1 == count {
+                true => target,
+                false => &step.to_version,
+            };
+            Self::_write_instance_version(&mut tx, to_version).await?;
+            tx.commit().await?;
+        }
+        Ok(end)
+    }
+    /// The queries being served right now, and the version of the spec they come from.
+    pub fn served(&self) -> Arc<Served> {
+        self.served.read().unwrap().clone()
+    }
+    /// Checks a spec on throwaway in-memory databases, and returns a report of every problem found.
+    /// The conversions are checked on an empty database; see `_check_conversions`. Then, on a database built by `init`,
+    /// every query is prepared, and every query example is run: write queries first, then read queries.
+    ///
+    /// Arguments:
+    ///
+    /// * `spec` - The spec to check.
+    pub async fn self_check(spec: &Spec) -> Result<Vec<String>, Error> {
+        info!("checking conversions");
+        let mut problems = Self::_check_conversions(spec).await?;
+        let db = Self::in_memory(spec).await?;
+        info!("checking queries against the DB");
+        problems.extend(
+            Self::_check_queries(
+                &mut *db.readers.acquire().await?,
+                &spec.queries,
+                spec.strict_cols,
+            )
+            .await?,
+        );
+        let mut names: Vec<&String> = spec.queries.write.keys().collect();
+        names.sort();
+        for name in names {
+            for (i, args) in spec.queries.write[name].examples.iter().enumerate() {
+                info!("running example {i} of write query {name}");
+                if let Err(e) = db.write_query(name, args, None).await {
+                    problems.push(format!("write query {name} example {i} failed: {e}"));
+                }
+            }
+        }
+        let mut names: Vec<&String> = spec.queries.read.keys().collect();
+        names.sort();
+        for name in names {
+            for (i, args) in spec.queries.read[name].examples.iter().enumerate() {
+                info!("running example {i} of read query {name}");
+                if let Err(e) = db.read_query(name, args, None).await {
+                    problems.push(format!("read query {name} example {i} failed: {e}"));
+                }
+            }
+        }
+        Ok(problems)
     }
     /// Executes a read-only query on the SQLite database and returns the result.
     ///
@@ -60,54 +622,45 @@ impl DB {
     ///
     /// * `query_name` - The code name of the query in the query lookup table.
     /// * `args` - Arguments to be bound to the query.
-    /// * `conn` - Can pass a `conn.read()` here to execute this method with a shared lock.
+    /// * `format` - Overrides the query's result format, if set.
     pub async fn read_query(
         &self,
         query_name: &str,
-        args: &HashMap<String, String>,
-        conn: Option<RwLockReadGuard<'_, Pool<Sqlite>>>,
-    ) -> Result<Vec<Vec<String>>, Error> {
-        let query = self
+        args: &HashMap<String, Value>,
+        format: Option<ResultFormat>,
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        debug!("waiting for a reader connection");
+        let mut conn = self.readers.acquire().await?;
+        self.run_read_query(&mut conn, query_name, args, format)
+            .await
+    }
+    /// Executes a read query on a connection the caller has already acquired.
+    async fn run_read_query(
+        &self,
+        conn: &mut SqliteConnection,
+        query_name: &str,
+        args: &HashMap<String, Value>,
+        format: Option<ResultFormat>,
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        let served = self.served();
+        let query = served
             .queries
             .read
             .get(query_name)
-            .ok_or_else(|| Error::QueryDoesNotExist)?;
-        if args.keys().len() == query.args.len() {
-            let conn = match conn {
-                Some(c) => {
-                    debug!("using shared read lock");
-                    c
-                }
-                None => {
-                    debug!("waiting for read lock");
-                    self.conn.read().await
-                }
-            };
-            let mut statement = sqlx::query(&query.sql_template);
-            for arg in query.args.iter() {
-                match args.get(arg) {
-                    Some(val) => {
-                        statement = statement.bind(val);
-                    }
-                    None => {
-                        statement = statement.bind("");
-                    }
-                }
-            }
-            let sql_res = statement.fetch_all(conn.deref()).await?;
-            let mut res = Vec::<Vec<String>>::new();
-            res.push(query.cols.clone());
-            for row in sql_res {
-                let mut v = Vec::<String>::new();
-                for c in 0..(row.len()) {
-                    v.push(row.try_get::<String, usize>(c).unwrap_or_default());
-                }
-                res.push(v);
-            }
-            Ok(res)
-        } else {
-            Err(Error::WrongNumberOfArgs)
+            .ok_or_else(|| Error::QueryDoesNotExist(query_name.to_owned()))?;
+        let vals = resolve_args(&query.args, args)?;
+        let mut statement = sqlx::query(&query.sql_template);
+        for val in vals {
+            statement = bind(val, statement);
         }
+        let sql_res = statement.fetch_all(&mut *conn).await?;
+        let format = format.unwrap_or(query.format);
+        let mut res = Vec::<Vec<Value>>::new();
+        res.push(query.cols.iter().map(|c| Value::from(c.as_str())).collect());
+        for row in sql_res {
+            res.push(row_to_json(&row, format, query.blob_encoding)?);
+        }
+        Ok(res)
     }
     /// Executes a read-only query on the SQLite database and returns the result.
     ///
@@ -115,25 +668,25 @@ impl DB {
     ///
     /// * `sql` - SQL statement to execute
     /// * `args` - Arguments to be bound to the query.
-    /// * `conn` - Can pass a `conn.read()` here to execute this method with a shared lock.
+    /// * `conn` - Can pass a reader connection here to execute this method on it.
     async fn _read_raw_query(
         &self,
         sql: &str,
-        conn: Option<RwLockReadGuard<'_, Pool<Sqlite>>>,
+        conn: Option<PoolConnection<Sqlite>>,
     ) -> Result<Vec<Vec<String>>, Error> {
-        let conn = match conn {
+        let mut conn = match conn {
             Some(c) => {
-                debug!("using shared read lock");
+                debug!("using given reader connection");
                 c
             }
             None => {
-                debug!("waiting for read lock");
-                self.conn.read().await
+                debug!("waiting for a reader connection");
+                self.readers.acquire().await?
             }
         };
         debug!("executing sql statement {sql}");
         let statement = sqlx::query(sql);
-        let sql_res = statement.fetch_all(conn.deref()).await?;
+        let sql_res = statement.fetch_all(&mut *conn).await?;
         let mut res = Vec::<Vec<String>>::new();
         for row in sql_res {
             let mut v = Vec::<String>::new();
@@ -148,27 +701,15 @@ impl DB {
     ///
     /// Arguments:
     ///
+    /// * `conn` - The connection to execute the query on.
     /// * `sql` - SQL statement to execute
-    /// * `args` - Arguments to be bound to the query.
-    /// * `conn` - Can pass a `conn.read()` here to execute this method with a shared lock.
-    pub async fn _read_one_raw_query(
-        &self,
+    async fn _read_one_raw_query(
+        conn: &mut SqliteConnection,
         sql: &str,
-        conn: Option<RwLockReadGuard<'_, Pool<Sqlite>>>,
     ) -> Result<Vec<String>, Error> {
-        let conn = match conn {
-            Some(c) => {
-                debug!("using shared read lock");
-                c
-            }
-            None => {
-                debug!("waiting for read lock");
-                self.conn.read().await
-            }
-        };
         debug!("executing sql statement {sql}");
         let statement = sqlx::query(sql);
-        let row = statement.fetch_one(conn.deref()).await?;
+        let row = statement.fetch_one(conn).await?;
         let mut res: Vec<String> = vec![];
         for c in 0..(row.len()) {
             res.push(row.try_get::<String, usize>(c).unwrap_or_default());
@@ -176,98 +717,342 @@ impl DB {
         Ok(res)
     }
     /// Executes a write-only query on the SQLite database and returns the result.
+    /// A query with `statements` runs them all in one transaction, and its result sums their `rows_affected` and keeps the rows of the last statement that returned any.
     ///
     /// Arguments:
     ///
     /// * `query_name` - The code name of the query in the query lookup table.
     /// * `args` - Arguments to be bound to the query.
-    /// * `conn` - Can pass a `conn.write()` here to execute this method with a shared lock.
+    /// * `format` - How rows returned by a `RETURNING` clause are rendered.
     pub async fn write_query(
         &self,
         query_name: &str,
-        args: &HashMap<String, String>,
-        conn: Option<RwLockWriteGuard<'_, Pool<Sqlite>>>,
-    ) -> Result<(), Error> {
-        let query = self
+        args: &HashMap<String, Value>,
+        format: Option<ResultFormat>,
+    ) -> Result<WriteResult, Error> {
+        debug!("waiting for the writer connection");
+        let mut conn = self.writer.lock().await;
+        self.run_write_query(&mut conn, query_name, args, format)
+            .await
+    }
+    /// Executes a write query on the writer connection, which the caller has already locked.
+    async fn run_write_query(
+        &self,
+        conn: &mut SqliteConnection,
+        query_name: &str,
+        args: &HashMap<String, Value>,
+        format: Option<ResultFormat>,
+    ) -> Result<WriteResult, Error> {
+        let served = self.served();
+        let query = served
             .queries
             .write
             .get(query_name)
-            .ok_or_else(|| Error::QueryDoesNotExist)?;
-        if args.keys().len() == query.args.len() {
-            let conn = match conn {
-                Some(c) => {
-                    debug!("using shared write lock");
-                    c
+            .ok_or_else(|| Error::QueryDoesNotExist(query_name.to_owned()))?;
+        let vals = resolve_args(&query.args, args)?;
+        if query.statements.is_empty() {
+            return execute_write(
+                conn,
+                &query.sql_template,
+                vals,
+                query.cols.as_ref(),
+                format.unwrap_or_default(),
+                query.blob_encoding,
+            )
+            .await;
+        }
+        // values that statements can bind: the query's args, then each named statement's results
+        let mut bound: HashMap<String, SqlValue> = query
+            .args
+            .iter()
+            .map(|a| a.name.clone())
+            .zip(vals)
+            .collect();
+        let mut res = WriteResult {
+            rows_affected: 0,
+            last_insert_rowid: None,
+            rows: None,
+        };
+        let mut tx = conn.begin().await?;
+        for (i, statement) in query.statements.iter().enumerate() {
+            let vals = statement
+                .args
+                .iter()
+                .map(|a| {
+                    bound
+                        .get(a)
+                        .cloned()
+                        .ok_or_else(|| Error::UnboundStatementArg {
+                            statement: i,
+                            arg: a.clone(),
+                        })
+                })
+                .collect::<Result<_, _>>()?;
+            let statement_res = execute_write(
+                &mut tx,
+                &statement.sql_template,
+                vals,
+                None,
+                format.unwrap_or_default(),
+                query.blob_encoding,
+            )
+            .await?;
+            if let Some(name) = &statement.name {
+                bound.insert(
+                    format!("{name}.rows_affected"),
+                    SqlValue::Integer(statement_res.rows_affected as i64),
+                );
+                bound.insert(
+                    format!("{name}.last_insert_rowid"),
+                    statement_res
+                        .last_insert_rowid
+                        .map_or(SqlValue::Null, SqlValue::Integer),
+                );
+                if let Some([header, first, ..]) = statement_res.rows.as_deref() {
+                    for (col, val) in header.iter().zip(first) {
+                        let col = col.as_str().unwrap_or_default();
+                        bound.insert(format!("{name}.{col}"), SqlValue::from(val));
+                    }
                 }
-                None => {
-                    debug!("waiting for write lock");
-                    self.conn.write().await
+            }
+            res.rows_affected += statement_res.rows_affected;
+            if statement_res.last_insert_rowid.is_some() {
+                res.last_insert_rowid = statement_res.last_insert_rowid;
+            }
+            if statement_res.rows.is_some() {
+                res.rows = statement_res.rows;
+            }
+        }
+        // dropping the transaction without committing rolls it back, so any error above undoes every statement
+        tx.commit().await?;
+        if let (Some(cols), Some(rows)) = (&query.cols, &mut res.rows) {
+            rows[0] = cols.iter().map(|c| Value::from(c.as_str())).collect();
+        }
+        Ok(res)
+    }
+    /// Executes a list of queries in one transaction, and returns one result per query.
+    /// A batch of only reads runs on a reader connection, and every read sees the same snapshot of the database.
+    /// A batch with writes runs on the writer connection, and its reads see the batch's earlier writes.
+    ///
+    /// Arguments:
+    ///
+    /// * `items` - The queries to execute, in order.
+    /// * `all_or_nothing` - If set, the first failed item rolls back the whole batch, and its error is returned.
+    ///   Otherwise each write is rolled back on its own when it fails, and its error is returned in its place.
+    /// * `format` - Overrides the queries' result format, if set.
+    pub(crate) async fn batch(
+        &self,
+        items: &[BatchItem],
+        all_or_nothing: bool,
+        format: Option<ResultFormat>,
+    ) -> Result<Vec<Result<BatchResult, Error>>, Error> {
+        let mut res = Vec::with_capacity(items.len());
+        if items.iter().all(|i| i.r#type == BatchItemType::Read) {
+            debug!("waiting for a reader connection");
+            let mut conn = self.readers.acquire().await?;
+            let mut tx = conn.begin().await?;
+            for (i, item) in items.iter().enumerate() {
+                let item_res = self
+                    .run_read_query(&mut tx, &item.query, &item.args, format)
+                    .await
+                    .map(BatchResult::Read);
+                match item_res {
+                    Err(error) if all_or_nothing => {
+                        return Err(Error::BatchItemFailed {
+                            item: i,
+                            error: Box::new(error),
+                        })
+                    }
+                    item_res => res.push(item_res),
                 }
+            }
+            tx.commit().await?;
+            return Ok(res);
+        }
+        debug!("waiting for the writer connection");
+        let mut conn = self.writer.lock().await;
+        let mut tx = conn.begin().await?;
+        for (i, item) in items.iter().enumerate() {
+            let item_res = match item.r#type {
+                BatchItemType::Read => self
+                    .run_read_query(&mut tx, &item.query, &item.args, format)
+                    .await
+                    .map(BatchResult::Read),
+                BatchItemType::Write => self
+                    .run_batch_write(&mut tx, &item.query, &item.args, all_or_nothing, format)
+                    .await
+                    .map(BatchResult::Write),
             };
-            let mut statement = sqlx::query(&query.sql_template);
-            for arg in query.args.iter() {
-                match args.get(arg) {
-                    Some(val) => {
-                        statement = statement.bind(val);
-                    }
-                    None => {
-                        statement = statement.bind("");
-                    }
+            match item_res {
+                Err(error) if all_or_nothing => {
+                    return Err(Error::BatchItemFailed {
+                        item: i,
+                        error: Box::new(error),
+                    })
                 }
+                item_res => res.push(item_res),
             }
-            statement.execute(conn.deref()).await?;
-            Ok(())
-        } else {
-            Err(Error::WrongNumberOfArgs)
         }
+        tx.commit().await?;
+        Ok(res)
     }
-    /// Execute a SQL statement that modifies the database
+    /// Executes a write query once per set of arguments, in one transaction, and returns one result per set.
     ///
-    /// Arguments
+    /// Arguments:
     ///
-    /// * `sql` - SQL statement to execute
-    /// * `conn` - Can pass a `conn.write()` here to execute this method with a shared lock.
-    /// TODO: This needs to take a vector of SQL statements
-    async fn _write_raw_query(
+    /// * `query_name` - The code name of the query in the query lookup table.
+    /// * `rows` - The sets of arguments to be bound to the query, in order.
+    /// * `all_or_nothing` - If set, the first failed write rolls back all of them, and its error is returned.
+    ///   Otherwise each write is rolled back on its own when it fails, and its error is returned in its place.
+    /// * `format` - How rows returned by a `RETURNING` clause are rendered.
+    pub async fn bulk_write(
         &self,
-        sql: &str,
-        conn: Option<RwLockWriteGuard<'_, Pool<Sqlite>>>,
-    ) -> Result<(), Error> {
-        let conn = match conn {
-            Some(c) => {
-                debug!("using shared write lock");
-                c
+        query_name: &str,
+        rows: &[HashMap<String, Value>],
+        all_or_nothing: bool,
+        format: Option<ResultFormat>,
+    ) -> Result<Vec<Result<WriteResult, Error>>, Error> {
+        if !self.served().queries.write.contains_key(query_name) {
+            return Err(Error::QueryDoesNotExist(query_name.to_owned()));
+        }
+        let mut res = Vec::with_capacity(rows.len());
+        debug!("waiting for the writer connection");
+        let mut conn = self.writer.lock().await;
+        let mut tx = conn.begin().await?;
+        for (i, args) in rows.iter().enumerate() {
+            match self
+                .run_batch_write(&mut tx, query_name, args, all_or_nothing, format)
+                .await
+            {
+                Err(error) if all_or_nothing => {
+                    return Err(Error::BatchItemFailed {
+                        item: i,
+                        error: Box::new(error),
+                    })
+                }
+                row_res => res.push(row_res),
             }
-            None => {
-                debug!("waiting for write lock");
-                self.conn.write().await
+        }
+        tx.commit().await?;
+        Ok(res)
+    }
+    /// Executes one write of a batch, inside the batch's transaction.
+    /// Unless the batch is all or nothing, the write runs in a savepoint, so that a failure only undoes itself.
+    async fn run_batch_write(
+        &self,
+        conn: &mut SqliteConnection,
+        query_name: &str,
+        args: &HashMap<String, Value>,
+        all_or_nothing: bool,
+        format: Option<ResultFormat>,
+    ) -> Result<WriteResult, Error> {
+        if all_or_nothing {
+            return self.run_write_query(conn, query_name, args, format).await;
+        }
+        // a nested transaction is a savepoint
+        let mut savepoint = conn.begin().await?;
+        match self
+            .run_write_query(&mut savepoint, query_name, args, format)
+            .await
+        {
+            Ok(res) => {
+                savepoint.commit().await?;
+                Ok(res)
             }
-        };
+            Err(error) => {
+                savepoint.rollback().await?;
+                Err(error)
+            }
+        }
+    }
+    /// Execute a SQL statement that modifies the database
+    ///
+    /// Arguments
+    ///
+    /// * `conn` - The writer connection, or a transaction on it.
+    /// * `sql` - SQL statement to execute
+    async fn _write_raw_query(conn: &mut SqliteConnection, sql: &str) -> Result<(), Error> {
         debug!("executing sql statement {sql}");
-        sqlx::query(&sql).execute(conn.deref()).await?;
+        sqlx::query(sql).execute(conn).await?;
         Ok(())
     }
     /// Initialize core Corolla sqlite tables
-    async fn _init_corolla_tables(&self, spec: &Spec) -> Result<(), Error> {
-        self._write_raw_query(
+    async fn _init_corolla_tables(conn: &mut SqliteConnection, spec: &Spec) -> Result<(), Error> {
+        Self::_write_raw_query(
+            conn,
             "create table if not exists corolla_db_info (key text unique not null, value text);",
-            None,
         )
         .await?;
-        self._write_instance_version(&spec.version).await?;
+        Self::_write_instance_version(conn, &spec.version).await?;
         Ok(())
     }
+    /// Prepares every query against the database, and returns a report of every problem found:
+    /// SQL that doesn't prepare, read queries that could change the database, and `args` or `cols` that don't match the SQL.
+    /// Warnings, such as `cols` naming a column differently from the SQL, are logged.
+    ///
+    /// Arguments:
+    ///
+    /// * `conn` - The connection to prepare the queries on.
+    /// * `queries` - The queries to check.
+    /// * `strict_cols` - Whether a column named differently in `cols` than in the SQL is a problem rather than a warning.
+    async fn _check_queries(
+        conn: &mut SqliteConnection,
+        queries: &Queries,
+        strict_cols: bool,
+    ) -> Result<Vec<String>, Error> {
+        let mut problems = vec![];
+        let mut names: Vec<&String> = queries.read.keys().collect();
+        names.sort();
+        for name in names {
+            let findings = match prepare(conn, &queries.read[name].sql_template).await {
+                Ok(statements) => check_read_query(&queries.read[name], &statements),
+                Err(Error::InvalidSpec(e)) => Findings {
+                    problems: vec![format!("does not prepare: {e}")],
+                    warnings: vec![],
+                },
+                Err(e) => return Err(e),
+            }
+            .strict(strict_cols);
+            for warning in &findings.warnings {
+                warn!("read query {name} {warning}");
+            }
+            problems.extend(
+                findings
+                    .problems
+                    .iter()
+                    .map(|p| format!("read query {name} {p}")),
+            );
+        }
+        let mut names: Vec<&String> = queries.write.keys().collect();
+        names.sort();
+        for name in names {
+            let findings = check_write_query(conn, &queries.write[name])
+                .await?
+                .strict(strict_cols);
+            for warning in &findings.warnings {
+                warn!("write query {name} {warning}");
+            }
+            problems.extend(
+                findings
+                    .problems
+                    .iter()
+                    .map(|p| format!("write query {name} {p}")),
+            );
+        }
+        Ok(problems)
+    }
     /// Get current DB instance version
-    async fn _instance_version(&self) -> Result<Option<InstanceVersion>, Error> {
-        let res = self
-            ._read_one_raw_query(
-                "select value from corolla_db_info where key = 'version';",
-                None,
-            )
-            .await;
+    async fn _instance_version(
+        conn: &mut SqliteConnection,
+    ) -> Result<Option<InstanceVersion>, Error> {
+        let res = Self::_read_one_raw_query(
+            conn,
+            "select value from corolla_db_info where key = 'version';",
+        )
+        .await;
         match res {
-            Ok(res) => match res.get(0) {
-                Some(val) => Ok(Some(InstanceVersion::from(val.as_ref()))),
+            Ok(res) => match res.first() {
+                Some(val) => Ok(Some(val.parse()?)),
                 None => Ok(None),
             },
             Err(err) => match err {
@@ -287,34 +1072,1060 @@ impl DB {
         }
     }
     /// Write current DB instance version
-    async fn _write_instance_version(&self, v: &Version) -> Result<(), Error> {
-        self._write_raw_query(
+    async fn _write_instance_version(
+        conn: &mut SqliteConnection,
+        v: &Version,
+    ) -> Result<(), Error> {
+        Self::_write_raw_query(
+            conn,
             &format!(
                 "insert or replace into corolla_db_info values ('version', '{}');",
                 v
             ),
-            None,
         )
         .await
     }
 
-    /// Run conversions specified by a spec.json file
-    async fn _run_conversions(&self, spec: &Spec, mut v: Version) -> Result<(), Error> {
-        info!("running DB conversions");
-        for (i, conversion) in spec.conversions.iter().enumerate() {
-            if v <= conversion.max_version {
-                info!("running conversion {i}");
-                for query in &conversion.queries {
-                    self._write_raw_query(&query, None).await?;
+    /// Create the migration ledger, which records every conversion applied to the database
+    async fn _init_migration_ledger(conn: &mut SqliteConnection) -> Result<(), Error> {
+        Self::_write_raw_query(
+            conn,
+            "create table if not exists corolla_migrations (conversion integer primary key, from_version text not null, to_version text not null, applied_at text not null, checksum text not null);",
+        )
+        .await
+    }
+    /// Checks every conversion in the migration ledger against the spec, and fails if any of them has been edited or removed since it was applied.
+    ///
+    /// Arguments:
+    ///
+    /// * `conn` - The writer connection, or a transaction on it.
+    /// * `spec` - The spec whose conversions are checked.
+    /// * `downgrading` - Whether the spec is older than the database, so that conversions past the end of the spec's were applied by a newer spec,
+    ///   and only the conversions the spec still has are checked.
+    async fn _check_applied_conversions(
+        conn: &mut SqliteConnection,
+        spec: &Spec,
+        downgrading: bool,
+    ) -> Result<(), Error> {
+        let applied =
+            sqlx::query("select conversion, checksum from corolla_migrations order by conversion;")
+                .fetch_all(&mut *conn)
+                .await?;
+        let mut problems = vec![];
+        for row in applied {
+            let i: i64 = row.try_get(0)?;
+            let checksum: String = row.try_get(1)?;
+            match usize::try_from(i)
+                .ok()
+                .and_then(|i| spec.conversions.get(i))
+            {
+                Some(conversion) if conversion.checksum() == checksum => (),
+                Some(_) => {
+                    problems.push(format!("conversion {i} has changed since it was applied"))
                 }
-                if v != conversion.new_version {
-                    v = conversion.new_version.clone();
-                    self._write_instance_version(&v).await?;
+                None if downgrading => {
+                    info!("conversion {i} was applied by a newer spec; leaving it in the ledger")
                 }
-            } else {
+                None => problems.push(format!(
+                    "conversion {i} was applied but is no longer in the spec"
+                )),
+            }
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(Error::ChangedConversions(problems)),
+        }
+    }
+    /// Run conversions specified by a spec.json file, each in its own transaction, and record each one in the migration ledger.
+    /// A conversion that fails is rolled back along with its version bump, so the database is never left half-converted.
+    async fn _run_conversions(
+        conn: &mut SqliteConnection,
+        spec: &Spec,
+        v: Version,
+    ) -> Result<(), Error> {
+        info!("running DB conversions");
+        for step in plan_conversions(spec, &v) {
+            let i = step.index;
+            if !step.runs {
                 info!("skipping conversion {i}");
+                continue;
+            }
+            info!("running conversion {i}");
+            let conversion = &spec.conversions[i];
+            let mut tx = conn.begin().await?;
+            for query in &conversion.queries {
+                Self::_write_raw_query(&mut tx, query).await?;
             }
+            sqlx::query(
+                "insert or replace into corolla_migrations values (?, ?, ?, datetime('now'), ?);",
+            )
+            .bind(i as i64)
+            .bind(step.from_version.to_string())
+            .bind(step.to_version.to_string())
+            .bind(conversion.checksum())
+            .execute(&mut *tx)
+            .await?;
+            if step.from_version != step.to_version {
+                Self::_write_instance_version(&mut tx, &step.to_version).await?;
+            }
+            tx.commit().await?;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    /// Opens a DB in a fresh temporary directory, which must outlive the DB.
+    async fn test_db(spec: serde_json::Value) -> (DB, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let spec: Spec = serde_json::from_value(spec).unwrap();
+        let path = dir.path().join("corolla-test.sqlite3");
+        let db = DB::from_spec(path.to_str().unwrap(), &spec, DbOptions::default())
+            .await
+            .unwrap();
+        (db, dir)
+    }
+
+    /// A spec of a table `t (x text)` with no queries, for tests of versions and conversions.
+    fn test_spec(version: [u64; 3], conversions: serde_json::Value) -> Spec {
+        serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": version,
+            "init": ["create table if not exists t (x text);"],
+            "queries": { "read": {}, "write": {} },
+            "conversions": conversions
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    /// args are matched by name, and optional args take their defaults
+    async fn args_by_name() {
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": ["create table t (a text, b text);"],
+            "queries": {
+                "read": {
+                    "read01": {
+                        "sql_template": "select a, b from t;",
+                        "args": [],
+                        "cols": ["a", "b"]
+                    }
+                },
+                "write": {
+                    "write01": {
+                        "sql_template": "insert into t values (?, ?);",
+                        "args": ["a", { "name": "b", "optional": true }]
+                    }
+                }
+            },
+            "conversions": []
+        }))
+        .await;
+        let args = HashMap::from([("a".to_owned(), json!("x"))]);
+        db.write_query("write01", &args, None).await.unwrap();
+        let args = HashMap::from([("c".to_owned(), json!("x"))]);
+        match db.write_query("write01", &args, None).await {
+            Err(Error::InvalidArgs {
+                missing,
+                unexpected,
+                ..
+            }) => {
+                assert_eq!(missing, vec!["a".to_owned()]);
+                assert_eq!(unexpected, vec!["c".to_owned()]);
+            }
+            _ => panic!("expected invalid args"),
+        }
+        let res = db
+            .read_query("read01", &HashMap::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            res,
+            vec![vec![json!("a"), json!("b")], vec![json!("x"), json!(null)]]
+        );
+    }
+
+    #[tokio::test]
+    /// typed args are coerced before they are bound
+    async fn typed_args() {
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": [
+                "create table t (id integer, name text);",
+                "insert into t values (1, 'one'), (2, 'two');"
+            ],
+            "queries": {
+                "read": {
+                    "read01": {
+                        "sql_template": "select name from t where id = ?;",
+                        "args": [{ "name": "id", "type": "integer", "min": 1 }],
+                        "cols": ["name"]
+                    }
+                },
+                "write": {}
+            },
+            "conversions": []
+        }))
+        .await;
+        let args = HashMap::from([("id".to_owned(), json!("2"))]);
+        let res = db.read_query("read01", &args, None).await.unwrap();
+        assert_eq!(res, vec![vec![json!("name")], vec![json!("two")]]);
+        let args = HashMap::from([("id".to_owned(), json!("0"))]);
+        match db.read_query("read01", &args, None).await {
+            Err(Error::ArgValidation(problems)) => {
+                assert_eq!(problems.get("id").unwrap(), "must be at least 1");
+            }
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    #[tokio::test]
+    /// write queries report what they changed, and can return rows
+    async fn write_results() {
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": [
+                "create table t (id integer primary key, name text);",
+                "create table b (data blob);"
+            ],
+            "queries": {
+                "read": {},
+                "write": {
+                    "insert": {
+                        "sql_template": "insert into t (name) values (?);",
+                        "args": ["name"]
+                    },
+                    "stamp": {
+                        "sql_template": "insert into b values (x'cafe') returning data;",
+                        "args": [],
+                        "blob_encoding": "hex"
+                    },
+                    "rename": {
+                        "sql_template": "update t set name = ? returning id, name;",
+                        "args": ["name"],
+                        "cols": ["id", "new_name"]
+                    },
+                    "count": {
+                        "sql_template": "select count(*) as n from t;",
+                        "args": []
+                    }
+                }
+            },
+            "conversions": []
+        }))
+        .await;
+        for (i, name) in ["a", "b"].iter().enumerate() {
+            let args = HashMap::from([("name".to_owned(), json!(name))]);
+            let res = db.write_query("insert", &args, None).await.unwrap();
+            assert_eq!(res.rows_affected, 1);
+            assert_eq!(res.last_insert_rowid, Some(i as i64 + 1));
+            assert!(res.rows.is_none());
+        }
+        let args = HashMap::from([("name".to_owned(), json!("c"))]);
+        let res = db.write_query("rename", &args, None).await.unwrap();
+        assert_eq!(res.rows_affected, 2);
+        // an update inserts nothing, so it doesn't report the earlier insert's rowid
+        assert_eq!(res.last_insert_rowid, None);
+        assert_eq!(
+            res.rows.unwrap(),
+            vec![
+                vec![json!("id"), json!("new_name")],
+                vec![json!(1), json!("c")],
+                vec![json!(2), json!("c")]
+            ]
+        );
+        // a select changes nothing, so it doesn't report the update's changes
+        let res = db
+            .write_query("count", &HashMap::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(res.rows_affected, 0);
+        assert_eq!(res.rows.unwrap(), vec![vec![json!("n")], vec![json!(2)]]);
+        let res = db
+            .write_query("stamp", &HashMap::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(res.last_insert_rowid, Some(1));
+        assert_eq!(
+            res.rows.unwrap(),
+            vec![vec![json!("data")], vec![json!("cafe")]]
+        );
+    }
+
+    #[tokio::test]
+    /// multi-statement writes share args and results, and roll back together
+    async fn multi_statement_writes() {
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": [
+                "create table people (id integer primary key, name text unique);",
+                "create table notes (person integer references people(id), note text check (note != ''));"
+            ],
+            "queries": {
+                "read": {
+                    "people": {
+                        "sql_template": "select name from people;",
+                        "args": [],
+                        "cols": ["name"]
+                    }
+                },
+                "write": {
+                    "add": {
+                        "args": ["name", "note"],
+                        "statements": [
+                            {
+                                "name": "person",
+                                "sql_template": "insert into people (name) values (?);",
+                                "args": ["name"]
+                            },
+                            {
+                                "sql_template": "insert into notes values (?, ?) returning note;",
+                                "args": ["person.last_insert_rowid", "note"]
+                            }
+                        ]
+                    },
+                    "note": {
+                        "args": ["name", "note"],
+                        "statements": [
+                            {
+                                "name": "person",
+                                "sql_template": "select id from people where name = ?;",
+                                "args": ["name"]
+                            },
+                            {
+                                "sql_template": "insert into notes values (?, ?);",
+                                "args": ["person.id", "note"]
+                            }
+                        ]
+                    }
+                }
+            },
+            "conversions": []
+        }))
+        .await;
+        let args = HashMap::from([
+            ("name".to_owned(), json!("ann")),
+            ("note".to_owned(), json!("hi")),
+        ]);
+        let res = db.write_query("add", &args, None).await.unwrap();
+        assert_eq!(res.rows_affected, 2);
+        assert_eq!(
+            res.rows.unwrap(),
+            vec![vec![json!("note")], vec![json!("hi")]]
+        );
+        // the second insert fails its check, so the first is rolled back
+        let args = HashMap::from([
+            ("name".to_owned(), json!("bob")),
+            ("note".to_owned(), json!("")),
+        ]);
+        let e = db.write_query("add", &args, None).await.unwrap_err();
+        assert_eq!(e.code(), "constraint_violation");
+        let res = db
+            .read_query("people", &HashMap::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(res, vec![vec![json!("name")], vec![json!("ann")]]);
+        // a lookup step changes nothing, so only the insert counts
+        let args = HashMap::from([
+            ("name".to_owned(), json!("ann")),
+            ("note".to_owned(), json!("again")),
+        ]);
+        let res = db.write_query("note", &args, None).await.unwrap();
+        assert_eq!(res.rows_affected, 1);
+        // a statement can't bind a column of a row that wasn't returned
+        let args = HashMap::from([
+            ("name".to_owned(), json!("cy")),
+            ("note".to_owned(), json!("hi")),
+        ]);
+        match db.write_query("note", &args, None).await {
+            Err(Error::UnboundStatementArg { statement, arg }) => {
+                assert_eq!((statement, arg.as_str()), (1, "person.id"));
+            }
+            _ => panic!("expected an unbound statement arg"),
+        }
+    }
+
+    #[tokio::test]
+    /// batches run in one transaction, all or nothing unless asked otherwise
+    async fn batches() {
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": ["create table t (x text unique);"],
+            "queries": {
+                "read": {
+                    "read01": {
+                        "sql_template": "select x from t;",
+                        "args": [],
+                        "cols": ["x"]
+                    }
+                },
+                "write": {
+                    "write01": {
+                        "sql_template": "insert into t values (?);",
+                        "args": ["x"]
+                    }
+                }
+            },
+            "conversions": []
+        }))
+        .await;
+        let items = |xs: &[&str]| -> Vec<BatchItem> {
+            let mut items: Vec<BatchItem> = xs
+                .iter()
+                .map(|x| {
+                    serde_json::from_value(
+                        json!({ "type": "write", "query": "write01", "args": { "x": x } }),
+                    )
+                    .unwrap()
+                })
+                .collect();
+            items.push(
+                serde_json::from_value(json!({ "type": "read", "query": "read01" })).unwrap(),
+            );
+            items
+        };
+        // reads see the batch's own writes
+        let res = db.batch(&items(&["a", "b"]), true, None).await.unwrap();
+        match res.last() {
+            Some(Ok(BatchResult::Read(rows))) => assert_eq!(rows.len(), 3),
+            _ => panic!("expected read results"),
+        }
+        // one failure rolls back the whole batch
+        match db.batch(&items(&["c", "a"]), true, None).await {
+            Err(Error::BatchItemFailed { item, .. }) => assert_eq!(item, 1),
+            _ => panic!("expected the batch to fail"),
+        }
+        // or only the failed item
+        let res = db.batch(&items(&["c", "a"]), false, None).await.unwrap();
+        assert!(res[0].is_ok());
+        assert!(res[1].is_err());
+        match res.last() {
+            Some(Ok(BatchResult::Read(rows))) => {
+                assert_eq!(
+                    rows[1..],
+                    [vec![json!("a")], vec![json!("b")], vec![json!("c")]]
+                )
+            }
+            _ => panic!("expected read results"),
+        }
+    }
+
+    #[tokio::test]
+    /// bulk writes run one write per set of args
+    async fn bulk_writes() {
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": ["create table t (x integer unique);"],
+            "queries": {
+                "read": {
+                    "count": {
+                        "sql_template": "select count(*) from t;",
+                        "args": [],
+                        "cols": ["n"]
+                    }
+                },
+                "write": {
+                    "write01": {
+                        "sql_template": "insert into t values (?);",
+                        "args": [{ "name": "x", "type": "integer" }]
+                    }
+                }
+            },
+            "conversions": []
+        }))
+        .await;
+        let rows: Vec<HashMap<String, Value>> = (0..1000)
+            .map(|x| HashMap::from([("x".to_owned(), json!(x))]))
+            .collect();
+        let res = db.bulk_write("write01", &rows, true, None).await.unwrap();
+        assert_eq!(res.len(), 1000);
+        let count =
+            || async { db.read_query("count", &HashMap::new(), None).await.unwrap()[1][0].clone() };
+        assert_eq!(count().await, json!(1000));
+        let rows: Vec<HashMap<String, Value>> = [1000, 1, 1001]
+            .iter()
+            .map(|x| HashMap::from([("x".to_owned(), json!(x))]))
+            .collect();
+        match db.bulk_write("write01", &rows, true, None).await {
+            Err(Error::BatchItemFailed { item, .. }) => assert_eq!(item, 1),
+            _ => panic!("expected the bulk write to fail"),
+        }
+        assert_eq!(count().await, json!(1000));
+        let res = db.bulk_write("write01", &rows, false, None).await.unwrap();
+        assert!(res[0].is_ok() && res[1].is_err() && res[2].is_ok());
+        assert_eq!(count().await, json!(1002));
+    }
+
+    #[tokio::test]
+    /// read queries that could change the database are rejected at startup, and so are renamed columns in a strict spec
+    async fn read_only_queries() {
+        let spec = |sql: &str| -> Spec {
+            serde_json::from_value(json!({
+                "spec_version": [1, 0, 0],
+                "version": [1, 0, 0],
+                "init": ["create table if not exists t (x text);"],
+                "queries": {
+                    "read": {
+                        "read01": { "sql_template": sql, "args": [], "cols": ["x"] }
+                    },
+                    "write": {}
+                },
+                "conversions": []
+            }))
+            .unwrap()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corolla-test.sqlite3");
+        let path = path.to_str().unwrap();
+        let db = DB::from_spec(path, &spec("select x from t;"), DbOptions::default()).await;
+        assert!(db.is_ok());
+        match DB::from_spec(
+            path,
+            &spec("delete from t returning x;"),
+            DbOptions::default(),
+        )
+        .await
+        {
+            Err(Error::QueryChecks(problems)) => {
+                assert_eq!(problems, vec!["read query read01 is not read-only"])
+            }
+            _ => panic!("expected the spec to be rejected"),
+        }
+        let mut renamed = spec("select x as y from t;");
+        assert!(DB::from_spec(path, &renamed, DbOptions::default())
+            .await
+            .is_ok());
+        renamed.strict_cols = true;
+        match DB::from_spec(path, &renamed, DbOptions::default()).await {
+            Err(Error::QueryChecks(problems)) => assert_eq!(
+                problems,
+                vec!["read query read01 column 0 is y in the SQL but x in cols"]
+            ),
+            _ => panic!("expected the spec to be rejected"),
+        }
+        // reader connections can't write either
+        let db = db.unwrap();
+        let mut conn = db.readers.acquire().await.unwrap();
+        let e = sqlx::query("insert into t values ('a');")
+            .execute(&mut *conn)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("readonly"), "{e}");
+    }
+
+    #[tokio::test]
+    /// self checks run the examples on an in-memory database built by `init`, and report every failure.
+    /// Conversions are replayed on an empty database, where only their syntax errors are problems
+    async fn self_checks() {
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 2],
+            "init": ["create table t (x text unique);"],
+            "queries": {
+                "read": {
+                    "read01": {
+                        "sql_template": "select x from t;",
+                        "args": [],
+                        "cols": ["x"],
+                        "examples": [{}, { "y": 1 }]
+                    }
+                },
+                "write": {
+                    "write01": {
+                        "sql_template": "insert into t values (?);",
+                        "args": ["x"],
+                        "examples": [{ "x": "a" }, { "x": "a" }]
+                    }
+                }
+            },
+            "conversions": [
+                {
+                    "max_version": [1, 0, 0],
+                    "new_version": [1, 0, 1],
+                    "queries": ["alter table t add column y text;", "alter table old add column y text;"]
+                },
+                {
+                    "max_version": [1, 0, 1],
+                    "new_version": [1, 0, 2],
+                    "queries": ["create table u (z text);", "alter tabel t add y;"]
+                }
+            ]
+        }))
+        .unwrap();
+        let problems = DB::self_check(&spec).await.unwrap();
+        assert_eq!(
+            problems,
+            vec![
+                "conversion 1 query 1 does not parse: near \"tabel\": syntax error".to_owned(),
+                "write query write01 example 1 failed: SQL error: error returned from database: (code: 2067) UNIQUE constraint failed: t.x".to_owned(),
+                "read query read01 example 1 failed: invalid args; unexpected: y".to_owned(),
+            ]
+        );
+        // conversions don't run on a new database, so a conversion that alters a table from init doesn't stop it being built,
+        // and each in-memory database is its own, and its readers can't write either
+        let db = DB::in_memory(&spec).await.unwrap();
+        let args = HashMap::from([("x".to_owned(), json!("b"))]);
+        db.write_query("write01", &args, None).await.unwrap();
+        let other = DB::in_memory(&spec).await.unwrap();
+        let res = other
+            .read_query("read01", &HashMap::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(res, vec![vec![json!("x")]]);
+        let mut conn = db.readers.acquire().await.unwrap();
+        let e = sqlx::query("insert into t values ('b');")
+            .execute(&mut *conn)
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("readonly"), "{e}");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    /// reads keep running while a long write holds the writer connection, and see the last committed state
+    async fn reads_during_long_write() {
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": ["create table t (x integer);"],
+            "queries": {
+                "read": {
+                    "count": {
+                        "sql_template": "select count(*) from t;",
+                        "args": [],
+                        "cols": ["n"]
+                    }
+                },
+                "write": {}
+            },
+            "conversions": []
+        }))
+        .await;
+        let (started, write_started) = tokio::sync::oneshot::channel();
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let writer = db.clone();
+        let write = tokio::spawn(async move {
+            let mut conn = writer.writer.lock().await;
+            let mut tx = conn.begin().await.unwrap();
+            sqlx::query(
+                "with recursive n(x) as (select 1 union all select x + 1 from n where x < 100000) insert into t select x from n;",
+            )
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+            started.send(()).unwrap();
+            // keep the write transaction open until every read below has finished
+            released.await.unwrap();
+            tx.commit().await.unwrap();
+        });
+        write_started.await.unwrap();
+        for _ in 0..100 {
+            let res = db.read_query("count", &HashMap::new(), None).await.unwrap();
+            assert_eq!(res[1][0], json!(0));
+        }
+        // the reads finished while the writer connection was still locked by the open write
+        assert!(db.writer.try_lock().is_err());
+        assert!(!write.is_finished());
+        release.send(()).unwrap();
+        write.await.unwrap();
+        let res = db.read_query("count", &HashMap::new(), None).await.unwrap();
+        assert_eq!(res[1][0], json!(100000));
+    }
+
+    #[tokio::test]
+    /// reloading a spec runs its conversions and swaps in its queries, unless they fail their checks
+    async fn reloads() {
+        let spec = |version: [u64; 3], conversions: serde_json::Value, sql: &str| Spec {
+            queries: serde_json::from_value(json!({
+                "read": {
+                    "read01": { "sql_template": sql, "args": [], "cols": ["x"] }
+                },
+                "write": {
+                    "write01": { "sql_template": "insert into t (x) values (?);", "args": ["x"] }
+                }
+            }))
+            .unwrap(),
+            ..test_spec(version, conversions)
+        };
+        let (db, _dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": ["create table if not exists t (x text);"],
+            "queries": {
+                "read": { "read01": { "sql_template": "select x from t;", "args": [], "cols": ["x"] } },
+                "write": { "write01": { "sql_template": "insert into t (x) values (?);", "args": ["x"] } }
+            },
+            "conversions": []
+        }))
+        .await;
+        let args = HashMap::from([("x".to_owned(), json!("a"))]);
+        db.write_query("write01", &args, None).await.unwrap();
+        // a conversion that adds a column the new query needs
+        let add_y = json!([{
+            "max_version": [1, 0, 0],
+            "new_version": [1, 1, 0],
+            "queries": ["alter table t add column y text default 'b';"]
+        }]);
+        let v2 = spec([1, 1, 0], add_y.clone(), "select y as x from t;");
+        db.reload(&v2).await.unwrap();
+        assert_eq!(db.served().version, Version::from([1, 1, 0]));
+        let rows = db
+            .read_query("read01", &HashMap::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![vec![json!("x")], vec![json!("b")]]);
+        // a query that doesn't prepare rolls back the new conversion, and leaves the old queries in place
+        let add_z = json!([{
+            "max_version": [1, 1, 0],
+            "new_version": [1, 2, 0],
+            "queries": ["alter table t add column z text;"]
+        }]);
+        let v3 = spec(
+            [1, 2, 0],
+            json!([add_y[0], add_z[0]]),
+            "select nope as x from t;",
+        );
+        match db.reload(&v3).await {
+            Err(Error::QueryChecks(problems)) => assert_eq!(
+                problems,
+                vec!["read query read01 does not prepare: no such column: nope"]
+            ),
+            _ => panic!("expected the spec to be rejected"),
+        }
+        assert_eq!(db.served().version, Version::from([1, 1, 0]));
+        assert_eq!(
+            DB::_instance_version(&mut *db.writer.lock().await)
+                .await
+                .unwrap(),
+            Some(Version::from([1, 1, 0]))
+        );
+        let rows = db
+            .read_query("read01", &HashMap::new(), None)
+            .await
+            .unwrap();
+        assert_eq!(rows, vec![vec![json!("x")], vec![json!("b")]]);
+        let cols = db._read_raw_query("select * from t;", None).await.unwrap();
+        assert_eq!(cols[0].len(), 2);
+    }
+
+    #[tokio::test]
+    /// each conversion runs in its own transaction and is recorded in the ledger, and applied conversions can't be edited
+    async fn conversion_ledger() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corolla-test.sqlite3");
+        let open = |spec: Spec| {
+            let path = path.to_str().unwrap().to_owned();
+            async move { DB::from_spec(&path, &spec, DbOptions::default()).await }
+        };
+        open(test_spec([1, 0, 0], json!([])))
+            .await
+            .unwrap()
+            .close()
+            .await
+            .unwrap();
+        let add_y = json!({
+            "max_version": [1, 0, 0],
+            "new_version": [1, 1, 0],
+            "queries": ["alter table t add column y text;"]
+        });
+        // the second conversion fails part way, so only the first one is applied
+        let add_z = json!({
+            "max_version": [1, 1, 0],
+            "new_version": [1, 2, 0],
+            "queries": ["alter table t add column z text;", "alter table nope add column w text;"]
+        });
+        match open(test_spec([1, 2, 0], json!([add_y, add_z]))).await {
+            Err(Error::SQL(_)) => (),
+            _ => panic!("expected the second conversion to fail"),
+        }
+        let db = open(test_spec([1, 1, 0], json!([add_y]))).await.unwrap();
+        assert_eq!(
+            DB::_instance_version(&mut *db.writer.lock().await)
+                .await
+                .unwrap(),
+            Some(Version::from([1, 1, 0]))
+        );
+        let cols = db
+            ._read_raw_query("select name from pragma_table_info('t');", None)
+            .await
+            .unwrap();
+        assert_eq!(cols, vec![vec!["x".to_owned()], vec!["y".to_owned()]]);
+        let applied = db
+            ._read_raw_query(
+                "select cast(conversion as text), from_version, to_version, checksum from corolla_migrations;",
+                None,
+            )
+            .await
+            .unwrap();
+        let checksum = test_spec([1, 1, 0], json!([add_y])).conversions[0].checksum();
+        assert_eq!(
+            applied,
+            vec![vec![
+                "0".to_owned(),
+                "1.0.0".to_owned(),
+                "1.1.0".to_owned(),
+                checksum
+            ]]
+        );
+        db.close().await.unwrap();
+        // editing or removing an applied conversion keeps the database from opening
+        let edited = json!({
+            "max_version": [1, 0, 0],
+            "new_version": [1, 1, 0],
+            "queries": ["alter table t add column y integer;"]
+        });
+        match open(test_spec([1, 1, 0], json!([edited]))).await {
+            Err(Error::ChangedConversions(problems)) => assert_eq!(
+                problems,
+                vec!["conversion 0 has changed since it was applied"]
+            ),
+            _ => panic!("expected the edited conversion to be refused"),
+        }
+        match open(test_spec([1, 1, 0], json!([]))).await {
+            Err(Error::ChangedConversions(problems)) => assert_eq!(
+                problems,
+                vec!["conversion 0 was applied but is no longer in the spec"]
+            ),
+            _ => panic!("expected the removed conversion to be refused"),
+        }
+    }
+
+    #[tokio::test]
+    /// a dry run applies the conversions to a copy of the database, and leaves the database itself alone
+    async fn dry_runs() {
+        let (db, dir) = test_db(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": ["create table if not exists t (x text);"],
+            "queries": { "read": {}, "write": {} },
+            "conversions": []
+        }))
+        .await;
+        let path = dir.path().join("corolla-test.sqlite3");
+        let path = path.to_str().unwrap();
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 1, 0],
+            "init": ["create table if not exists t (x text);"],
+            "queries": {
+                "read": { "read01": { "sql_template": "select y from t;", "args": [], "cols": ["y"] } },
+                "write": {}
+            },
+            "conversions": [{
+                "max_version": [1, 0, 0],
+                "new_version": [1, 1, 0],
+                "queries": ["alter table t add column y text;"]
+            }]
+        }))
+        .unwrap();
+        let schema = DB::dry_run(path, &spec, None, DbOptions::default())
+            .await
+            .unwrap();
+        assert!(schema.contains(&"CREATE TABLE t (x text, y text)".to_owned()));
+        // the copy's directory is gone
+        let leftovers = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                name.to_string_lossy().starts_with("corolla-dry-run-")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+        assert_eq!(
+            DB::read_version(path, Duration::from_secs(5))
+                .await
+                .unwrap(),
+            Some(Version::from([1, 0, 0]))
+        );
+        let cols = db
+            ._read_raw_query("select name from pragma_table_info('t');", None)
+            .await
+            .unwrap();
+        assert_eq!(cols, vec![vec!["x".to_owned()]]);
+        assert_eq!(
+            DB::read_version(
+                dir.path().join("missing.sqlite3").to_str().unwrap(),
+                Duration::from_secs(5)
+            )
+            .await
+            .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    /// rolling back undoes conversions newest first, and refuses to start if one of them can't be undone
+    async fn rollbacks() {
+        let add_y = json!({
+            "max_version": [1, 0, 0],
+            "new_version": [1, 1, 0],
+            "queries": ["alter table t add column y text;"],
+            "down_queries": ["alter table t drop column y;"]
+        });
+        let add_u = json!({
+            "max_version": [1, 1, 0],
+            "new_version": [1, 2, 0],
+            "queries": ["create table u (z text);"]
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corolla-test.sqlite3");
+        let path = path.to_str().unwrap();
+        let timeout = Duration::from_secs(5);
+        let schema = || async {
+            let mut conn = SqliteConnectOptions::new()
+                .filename(path)
+                .connect()
+                .await
+                .unwrap();
+            let schema: Vec<String> = sqlx::query_scalar(
+                "select sql from sqlite_schema where name in ('t', 'u') order by name;",
+            )
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+            schema
+        };
+        for s in [
+            test_spec([1, 0, 0], json!([])),
+            test_spec([1, 2, 0], json!([add_y, add_u])),
+        ] {
+            DB::from_spec(path, &s, DbOptions::default())
+                .await
+                .unwrap()
+                .close()
+                .await
+                .unwrap();
+        }
+        let v2 = test_spec([1, 2, 0], json!([add_y, add_u]));
+        match DB::roll_back(path, &v2, &Version::from([1, 0, 0]), timeout).await {
+            Err(Error::RollbackRefused(reason)) => assert_eq!(
+                reason,
+                "conversions on the way back to 1.0.0 have no down_queries: 1"
+            ),
+            _ => panic!("expected the rollback to be refused"),
+        }
+        assert_eq!(
+            DB::read_version(path, timeout).await.unwrap(),
+            Some(Version::from([1, 2, 0]))
+        );
+        // down_queries can be added to a conversion after it has been applied
+        let mut add_u = add_u;
+        add_u["down_queries"] = json!(["drop table u;"]);
+        let v2 = test_spec([1, 2, 0], json!([add_y, add_u]));
+        assert_eq!(
+            DB::roll_back(path, &v2, &Version::from([1, 1, 0]), timeout)
+                .await
+                .unwrap(),
+            Version::from([1, 1, 0])
+        );
+        assert_eq!(schema().await, vec!["CREATE TABLE t (x text, y text)"]);
+        // undoing a conversion can't stop between its max_version and new_version
+        match DB::roll_back(path, &v2, &Version::from([1, 0, 5]), timeout).await {
+            Err(Error::RollbackRefused(reason)) => {
+                assert_eq!(reason, "undoing conversions goes past 1.0.5, to 1.0.0")
+            }
+            _ => panic!("expected the rollback to be refused"),
+        }
+        assert_eq!(schema().await, vec!["CREATE TABLE t (x text, y text)"]);
+        assert_eq!(
+            DB::roll_back(path, &v2, &Version::from([1, 0, 0]), timeout)
+                .await
+                .unwrap(),
+            Version::from([1, 0, 0])
+        );
+        assert_eq!(schema().await, vec!["CREATE TABLE t (x text)"]);
+        match DB::roll_back(path, &v2, &Version::from([1, 1, 0]), timeout).await {
+            Err(Error::RollbackRefused(reason)) => {
+                assert_eq!(reason, "the database is at version 1.0.0, below 1.1.0")
+            }
+            _ => panic!("expected the rollback to be refused"),
+        }
+        DB::from_spec(path, &v2, DbOptions::default())
+            .await
+            .unwrap()
+            .close()
+            .await
+            .unwrap();
+        assert_eq!(
+            schema().await,
+            vec!["CREATE TABLE t (x text, y text)", "CREATE TABLE u (z text)"]
+        );
+    }
+
+    #[tokio::test]
+    /// a spec older than the database is refused, on startup and on reloads, unless downgrades are allowed
+    async fn downgrades() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corolla-test.sqlite3");
+        let path = path.to_str().unwrap();
+        let timeout = Duration::from_secs(5);
+        // the newer spec has a conversion that the older one doesn't
+        let add_y = json!({
+            "max_version": [1, 0, 0],
+            "new_version": [1, 1, 0],
+            "queries": ["alter table t add column y text;"]
+        });
+        let v2 = test_spec([1, 1, 0], json!([add_y]));
+        DB::from_spec(path, &test_spec([1, 0, 0], json!([])), DbOptions::default())
+            .await
+            .unwrap()
+            .close()
+            .await
+            .unwrap();
+        let db = DB::from_spec(path, &v2, DbOptions::default())
+            .await
+            .unwrap();
+        match db.reload(&test_spec([1, 0, 0], json!([]))).await {
+            Err(Error::DatabaseNewerThanSpec { database, spec }) => assert_eq!(
+                (database, spec),
+                (Version::from([1, 1, 0]), Version::from([1, 0, 0]))
+            ),
+            _ => panic!("expected the older spec to be refused"),
+        }
+        db.close().await.unwrap();
+        match DB::from_spec(path, &test_spec([1, 0, 0], json!([])), DbOptions::default()).await {
+            Err(Error::DatabaseNewerThanSpec { .. }) => (),
+            _ => panic!("expected the older spec to be refused"),
+        }
+        assert_eq!(
+            DB::read_version(path, timeout).await.unwrap(),
+            Some(Version::from([1, 1, 0]))
+        );
+        DB::from_spec(
+            path,
+            &test_spec([1, 0, 0], json!([])),
+            DbOptions::default().allow_downgrade(true),
+        )
+        .await
+        .unwrap()
+        .close()
+        .await
+        .unwrap();
+        assert_eq!(
+            DB::read_version(path, timeout).await.unwrap(),
+            Some(Version::from([1, 0, 0]))
+        );
+        // the downgrade leaves the newer spec's conversion in the ledger
+        let mut conn = SqliteConnectOptions::new()
+            .filename(path)
+            .connect()
+            .await
+            .unwrap();
+        let applied: Vec<i64> = sqlx::query_scalar("select conversion from corolla_migrations;")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(applied, vec![0]);
+    }
+}
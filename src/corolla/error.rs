@@ -1,14 +1,72 @@
-use axum::{http::StatusCode, response::IntoResponse};
+use axum::{
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use corolla_spec::{InstanceVersion, ParseVersionError, SpecError};
+use log::error;
+use serde::Serialize;
+use serde_json::{json, Value};
+use sqlx::error::ErrorKind;
+use std::collections::HashMap;
 
 /// An error type for a SQLite DB. Wraps several types of errors and implements axum_core::response::into_response::IntoResponse.
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Error {
     File(std::io::Error),
     JSON(serde_json::Error),
     Server,
     SQL(sqlx::Error),
-    QueryDoesNotExist,
-    WrongNumberOfArgs,
+    /// The named query is not in the spec.
+    QueryDoesNotExist(String),
+    /// The request's arguments don't match the query's declared args.
+    InvalidArgs {
+        expected: Vec<String>,
+        missing: Vec<String>,
+        unexpected: Vec<String>,
+    },
+    /// Some of the request's arguments failed their declared type or constraints. Maps arg names to problems.
+    ArgValidation(HashMap<String, String>),
+    UnknownResultFormat(String),
+    /// The request's body isn't JSON of the shape the endpoint expects.
+    InvalidBody(String),
+    /// The request's URL query string can't be parsed.
+    InvalidQueryString(String),
+    /// The spec.json has a mistake in it.
+    InvalidSpec(String),
+    /// The spec's queries don't match the database, or failed to run in `--test` mode. Lists every problem found.
+    QueryChecks(Vec<String>),
+    /// A statement of a multi-statement write query binds a result that an earlier statement didn't produce,
+    /// e.g. a column of a `RETURNING` clause that returned no rows.
+    UnboundStatementArg {
+        statement: usize,
+        arg: String,
+    },
+    /// Some of the spec's test cases failed. Lists every failure.
+    TestFailures(Vec<String>),
+    /// Conversions already applied to the database have been edited or removed from the spec since. Lists every one.
+    ChangedConversions(Vec<String>),
+    /// A version isn't numbers separated by dots.
+    Version(ParseVersionError),
+    /// The database has been converted by a newer spec than the one being served.
+    DatabaseNewerThanSpec {
+        database: InstanceVersion,
+        spec: InstanceVersion,
+    },
+    /// `corolla migrate --to` can't roll the database back to the version asked for.
+    RollbackRefused(String),
+    /// A query hook refused to run the query.
+    Rejected {
+        status: StatusCode,
+        message: String,
+    },
+    /// An item of an all-or-nothing batch failed, so the whole batch was rolled back.
+    BatchItemFailed {
+        item: usize,
+        error: Box<Error>,
+    },
 }
 
 impl From<std::io::Error> for Error {
@@ -23,18 +81,303 @@ impl From<serde_json::Error> for Error {
     }
 }
 
+impl From<SpecError> for Error {
+    fn from(e: SpecError) -> Self {
+        match e {
+            SpecError::File(e) => Error::File(e),
+            SpecError::JSON(e) => Error::JSON(e),
+            SpecError::Invalid(problem) => Error::InvalidSpec(problem),
+        }
+    }
+}
+
+impl From<JsonRejection> for Error {
+    fn from(e: JsonRejection) -> Self {
+        Error::InvalidBody(e.body_text())
+    }
+}
+
+impl From<QueryRejection> for Error {
+    fn from(e: QueryRejection) -> Self {
+        Error::InvalidQueryString(e.body_text())
+    }
+}
+
+impl From<ParseVersionError> for Error {
+    fn from(e: ParseVersionError) -> Self {
+        Error::Version(e)
+    }
+}
+
 impl From<sqlx::Error> for Error {
     fn from(e: sqlx::Error) -> Self {
         Error::SQL(e)
     }
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::File(e) => write!(f, "file error: {e}"),
+            Error::JSON(e) => write!(f, "JSON error: {e}"),
+            Error::Server => write!(f, "server error"),
+            Error::SQL(e) => write!(f, "SQL error: {e}"),
+            Error::QueryDoesNotExist(query) => write!(f, "query {query} does not exist"),
+            Error::InvalidArgs {
+                missing,
+                unexpected,
+                ..
+            } => {
+                write!(f, "invalid args")?;
+                if !missing.is_empty() {
+                    write!(f, "; missing: {}", missing.join(", "))?;
+                }
+                if !unexpected.is_empty() {
+                    write!(f, "; unexpected: {}", unexpected.join(", "))?;
+                }
+                Ok(())
+            }
+            Error::ArgValidation(problems) => {
+                let mut problems: Vec<String> = problems
+                    .iter()
+                    .map(|(arg, problem)| format!("{arg} {problem}"))
+                    .collect();
+                problems.sort();
+                write!(f, "invalid arg values: {}", problems.join("; "))
+            }
+            Error::UnknownResultFormat(format) => write!(f, "unknown result format {format}"),
+            Error::InvalidBody(reason) | Error::InvalidQueryString(reason) => write!(f, "{reason}"),
+            Error::InvalidSpec(problem) => write!(f, "invalid spec: {problem}"),
+            Error::QueryChecks(problems) => {
+                write!(f, "spec failed {} checks:", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {problem}")?;
+                }
+                Ok(())
+            }
+            Error::UnboundStatementArg { statement, arg } => {
+                write!(f, "statement {statement} binds {arg}, which has no value")
+            }
+            Error::TestFailures(failures) => {
+                write!(f, "{} spec tests failed:", failures.len())?;
+                for failure in failures {
+                    write!(f, "\n  {failure}")?;
+                }
+                Ok(())
+            }
+            Error::ChangedConversions(problems) => {
+                write!(
+                    f,
+                    "{} applied conversions no longer match the spec:",
+                    problems.len()
+                )?;
+                for problem in problems {
+                    write!(f, "\n  {problem}")?;
+                }
+                Ok(())
+            }
+            Error::Version(e) => write!(f, "{e}"),
+            Error::DatabaseNewerThanSpec { database, spec } => write!(
+                f,
+                "the database is at version {database}, newer than the spec's version {spec}; \
+                 use a newer spec, roll the database back with `corolla migrate --to {spec}`, \
+                 or pass --allow-downgrade to record the older version anyway"
+            ),
+            Error::RollbackRefused(reason) => write!(f, "can't roll back: {reason}"),
+            Error::Rejected { message, .. } => write!(f, "{message}"),
+            Error::BatchItemFailed { item, error } => {
+                write!(f, "batch item {item} failed: {error}")
+            }
+        }
+    }
+}
+
+/// The JSON body of an error response.
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    /// A machine-readable error code, e.g. `query_not_found`.
+    code: &'static str,
+    /// A human-readable description of the error.
+    message: String,
+    /// Extra information about the error, e.g. the args a query expects.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
+
+impl Error {
+    /// The database constraint violated by a failed statement, if any.
+    fn constraint(&self) -> Option<&'static str> {
+        match self {
+            Error::SQL(sqlx::Error::Database(e)) => match e.kind() {
+                ErrorKind::UniqueViolation => Some("unique"),
+                ErrorKind::ForeignKeyViolation => Some("foreign_key"),
+                ErrorKind::NotNullViolation => Some("not_null"),
+                ErrorKind::CheckViolation => Some("check"),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The HTTP status code this error is reported with.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::BatchItemFailed { error, .. } => error.status(),
+            Error::QueryDoesNotExist(_) => StatusCode::NOT_FOUND,
+            Error::Rejected { status, .. } => *status,
+            Error::InvalidArgs { .. }
+            | Error::UnknownResultFormat(_)
+            | Error::InvalidBody(_)
+            | Error::InvalidQueryString(_) => StatusCode::BAD_REQUEST,
+            Error::ArgValidation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::SQL(_) if self.constraint().is_some() => StatusCode::CONFLICT,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The machine-readable code this error is reported with.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::BatchItemFailed { error, .. } => error.code(),
+            Error::QueryDoesNotExist(_) => "query_not_found",
+            Error::Rejected { .. } => "rejected",
+            Error::InvalidArgs { .. } => "invalid_args",
+            Error::ArgValidation(_) => "validation_failed",
+            Error::UnknownResultFormat(_) => "unknown_result_format",
+            Error::InvalidBody(_) => "invalid_body",
+            Error::InvalidQueryString(_) => "invalid_query_string",
+            Error::SQL(_) if self.constraint().is_some() => "constraint_violation",
+            _ => "internal_error",
+        }
+    }
+
+    /// Describes the error as the JSON body of a response.
+    ///
+    /// Arguments:
+    ///
+    /// * `debug` - Whether to include SQL error messages. These can leak table and column names, so they are hidden by default.
+    pub fn body(&self, debug: bool) -> ErrorBody {
+        let (message, details) = match self {
+            Error::BatchItemFailed { item, error } => {
+                let body = error.body(debug);
+                (
+                    format!("batch item {item} failed: {}", body.message),
+                    Some(json!({ "item": item, "details": body.details })),
+                )
+            }
+            Error::InvalidArgs {
+                expected,
+                missing,
+                unexpected,
+            } => (
+                self.to_string(),
+                Some(json!({ "expected": expected, "missing": missing, "unexpected": unexpected })),
+            ),
+            Error::UnknownResultFormat(format) => (
+                self.to_string(),
+                Some(json!({ "format": format, "expected": ["typed", "strings"] })),
+            ),
+            Error::ArgValidation(problems) => (self.to_string(), Some(json!(problems))),
+            Error::QueryDoesNotExist(_)
+            | Error::Rejected { .. }
+            | Error::InvalidBody(_)
+            | Error::InvalidQueryString(_) => (self.to_string(), None),
+            Error::SQL(_) if self.constraint().is_some() => {
+                let mut details = json!({ "constraint": self.constraint() });
+                if debug {
+                    details["sql_error"] = Value::from(self.to_string());
+                }
+                (
+                    "a database constraint was violated".to_owned(),
+                    Some(details),
+                )
+            }
+            _ if debug => (self.to_string(), None),
+            _ => ("there was a problem running your query".to_owned(), None),
+        };
+        ErrorBody {
+            code: self.code(),
+            message,
+            details,
+        }
+    }
+
+    /// Converts the error into an HTTP response with a JSON body.
+    ///
+    /// Arguments:
+    ///
+    /// * `debug` - Whether to include SQL error messages in the response.
+    pub fn into_response_with_details(self, debug: bool) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            error!("{self}");
+        }
+        (status, Json(self.body(debug))).into_response()
+    }
+}
+
 impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "there was a problem running your query",
-        )
-            .into_response()
+    fn into_response(self) -> Response {
+        self.into_response_with_details(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    /// client mistakes are reported with 4xx status codes
+    fn client_errors() {
+        let e = Error::QueryDoesNotExist("read01".to_owned());
+        assert_eq!(e.status(), StatusCode::NOT_FOUND);
+        assert_eq!(e.code(), "query_not_found");
+        let e = Error::InvalidArgs {
+            expected: vec!["id".to_owned()],
+            missing: vec!["id".to_owned()],
+            unexpected: vec![],
+        };
+        assert_eq!(e.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(e.code(), "invalid_args");
+        assert_eq!(e.to_string(), "invalid args; missing: id");
+        let e = Error::Rejected {
+            status: StatusCode::FORBIDDEN,
+            message: "members only".to_owned(),
+        };
+        assert_eq!(e.status(), StatusCode::FORBIDDEN);
+        assert_eq!(e.code(), "rejected");
+        assert_eq!(e.body(false).message, "members only");
+        let e = Error::InvalidBody("expected value at line 1 column 1".to_owned());
+        assert_eq!(e.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(e.code(), "invalid_body");
+        assert_eq!(e.body(false).message, "expected value at line 1 column 1");
+        let e = Error::Server;
+        assert_eq!(e.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(e.code(), "internal_error");
+    }
+
+    #[tokio::test]
+    /// constraint violations are reported as conflicts
+    async fn constraint_errors() {
+        use sqlx::{Connection, SqliteConnection};
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("create table t (x text unique);")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        sqlx::query("insert into t values ('a');")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let e = Error::from(
+            sqlx::query("insert into t values ('a');")
+                .execute(&mut conn)
+                .await
+                .unwrap_err(),
+        );
+        assert_eq!(e.status(), StatusCode::CONFLICT);
+        assert_eq!(e.code(), "constraint_violation");
+        assert_eq!(e.constraint(), Some("unique"));
     }
 }
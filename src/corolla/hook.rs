@@ -0,0 +1,57 @@
+/// This file contains the extension point that lets an embedding application run its own code around queries.
+use super::{db::WriteResult, error::Error, Args};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use serde_json::Value;
+
+/// Code that runs around every read and write query served over HTTP, including the queries of bulk writes and batches.
+/// Hooks are registered with [`RouterBuilder::hook`](super::RouterBuilder::hook) and run in the order they were registered.
+///
+/// A `before_*` method can rewrite the args, or veto the query by returning an error such as [`Error::Rejected`].
+/// An `after_*` method can observe or rewrite the results; an error from it fails the response,
+/// though a write has been committed by then. Every method does nothing by default.
+///
+/// Arguments passed to every method:
+///
+/// * `query` - The code name of the query in the query lookup table.
+/// * `headers` - The HTTP request's headers, e.g. for authorization.
+#[async_trait]
+pub trait QueryHook: Send + Sync {
+    async fn before_read(
+        &self,
+        _query: &str,
+        _headers: &HeaderMap,
+        _args: &mut Args,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn after_read(
+        &self,
+        _query: &str,
+        _headers: &HeaderMap,
+        _args: &Args,
+        _rows: &mut Vec<Vec<Value>>,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn before_write(
+        &self,
+        _query: &str,
+        _headers: &HeaderMap,
+        _args: &mut Args,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn after_write(
+        &self,
+        _query: &str,
+        _headers: &HeaderMap,
+        _args: &Args,
+        _result: &mut WriteResult,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
@@ -1,99 +1,788 @@
-use self::{
-    error::Error,
-    spec::{read_spec, Spec},
+use self::{error::Error, value::parse_format};
+use crate::corolla::{
+    db::{BatchResult, DbOptions, WriteResult, DB},
+    hook::QueryHook,
 };
-use crate::corolla::db::DB;
 use axum::{
-    extract::{Path, Query, State},
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::HeaderMap,
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use log::info;
-use std::collections::HashMap;
+use corolla_spec::{read_spec, BatchItem, BatchItemType, InstanceVersion, ResultFormat};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    future::Future,
+    io::{self, Write},
+    process,
+    sync::Arc,
+    time::Duration,
+};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::Notify;
 use tower_http::services::ServeDir;
 
-mod db;
-mod error;
-mod spec;
-mod version;
+pub(crate) mod args;
+mod check;
+mod codegen;
+pub(crate) mod db;
+pub(crate) mod error;
+pub(crate) mod hook;
+mod openapi;
+mod plan;
+mod testing;
+mod value;
+
+pub type Args = HashMap<String, Value>;
+
+/// Request header that overrides a query's result format, e.g. `x-corolla-format: strings`.
+const FORMAT_HEADER: &str = "x-corolla-format";
+
+/// Reads the result format requested by the client, if any.
+fn requested_format(headers: &HeaderMap) -> Result<Option<ResultFormat>, Error> {
+    match headers.get(FORMAT_HEADER) {
+        Some(val) => {
+            let val = val
+                .to_str()
+                .map_err(|_| Error::UnknownResultFormat(format!("{val:?}")))?;
+            Ok(Some(parse_format(val)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// State shared by the HTTP endpoints.
+#[derive(Clone)]
+struct AppState {
+    db: DB,
+    /// Whether error responses include SQL error details.
+    debug: bool,
+    /// The base HTTP route the endpoints are served under, for the OpenAPI document.
+    route_base: Arc<str>,
+    /// Hooks that run around every query, in order.
+    hooks: Arc<Vec<Box<dyn QueryHook>>>,
+}
+
+impl AppState {
+    /// Runs a read query between the `before_read` and `after_read` hooks.
+    async fn read(
+        &self,
+        query: &str,
+        headers: &HeaderMap,
+        mut args: Args,
+        format: Option<ResultFormat>,
+    ) -> Result<Vec<Vec<Value>>, Error> {
+        for hook in self.hooks.iter() {
+            hook.before_read(query, headers, &mut args).await?;
+        }
+        let mut rows = self.db.read_query(query, &args, format).await?;
+        for hook in self.hooks.iter() {
+            hook.after_read(query, headers, &args, &mut rows).await?;
+        }
+        Ok(rows)
+    }
+
+    /// Runs a write query between the `before_write` and `after_write` hooks.
+    async fn write(
+        &self,
+        query: &str,
+        headers: &HeaderMap,
+        mut args: Args,
+        format: Option<ResultFormat>,
+    ) -> Result<WriteResult, Error> {
+        for hook in self.hooks.iter() {
+            hook.before_write(query, headers, &mut args).await?;
+        }
+        let mut res = self.db.write_query(query, &args, format).await?;
+        for hook in self.hooks.iter() {
+            hook.after_write(query, headers, &args, &mut res).await?;
+        }
+        Ok(res)
+    }
+
+    /// Runs a bulk write, with the `before_write` hooks run for every set of args first.
+    /// A set of args the hooks veto fails the whole bulk write if `all_or_nothing` is set,
+    /// and is otherwise skipped, with the veto as its result.
+    async fn bulk_write(
+        &self,
+        query: &str,
+        headers: &HeaderMap,
+        rows: Vec<Args>,
+        all_or_nothing: bool,
+        format: Option<ResultFormat>,
+    ) -> Result<Vec<Result<WriteResult, Error>>, Error> {
+        let mut vetoes = Vec::with_capacity(rows.len());
+        let mut accepted = Vec::with_capacity(rows.len());
+        for (item, mut args) in rows.into_iter().enumerate() {
+            let mut veto = None;
+            for hook in self.hooks.iter() {
+                if let Err(e) = hook.before_write(query, headers, &mut args).await {
+                    veto = Some(e);
+                    break;
+                }
+            }
+            match veto {
+                Some(e) if all_or_nothing => {
+                    return Err(Error::BatchItemFailed {
+                        item,
+                        error: Box::new(e),
+                    })
+                }
+                Some(e) => vetoes.push(Some(e)),
+                None => {
+                    vetoes.push(None);
+                    accepted.push(args);
+                }
+            }
+        }
+        let rows = accepted;
+        let mut res = self
+            .db
+            .bulk_write(query, &rows, all_or_nothing, format)
+            .await?;
+        for (args, item_res) in rows.iter().zip(res.iter_mut()) {
+            if let Ok(write_res) = item_res {
+                for hook in self.hooks.iter() {
+                    if let Err(e) = hook.after_write(query, headers, args, write_res).await {
+                        *item_res = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(with_vetoes(vetoes, res))
+    }
+
+    /// Runs a batch, with the `before_*` hooks run for every item first.
+    /// An item the hooks veto fails the whole batch if `all_or_nothing` is set,
+    /// and is otherwise skipped, with the veto as its result.
+    async fn batch(
+        &self,
+        headers: &HeaderMap,
+        items: Vec<BatchItem>,
+        all_or_nothing: bool,
+        format: Option<ResultFormat>,
+    ) -> Result<Vec<Result<BatchResult, Error>>, Error> {
+        let mut vetoes = Vec::with_capacity(items.len());
+        let mut accepted = Vec::with_capacity(items.len());
+        for (i, mut item) in items.into_iter().enumerate() {
+            let mut veto = None;
+            for hook in self.hooks.iter() {
+                let res = match item.r#type {
+                    BatchItemType::Read => {
+                        hook.before_read(&item.query, headers, &mut item.args).await
+                    }
+                    BatchItemType::Write => {
+                        hook.before_write(&item.query, headers, &mut item.args)
+                            .await
+                    }
+                };
+                if let Err(e) = res {
+                    veto = Some(e);
+                    break;
+                }
+            }
+            match veto {
+                Some(e) if all_or_nothing => {
+                    return Err(Error::BatchItemFailed {
+                        item: i,
+                        error: Box::new(e),
+                    })
+                }
+                Some(e) => vetoes.push(Some(e)),
+                None => {
+                    vetoes.push(None);
+                    accepted.push(item);
+                }
+            }
+        }
+        let items = accepted;
+        let mut res = self.db.batch(&items, all_or_nothing, format).await?;
+        for (item, item_res) in items.iter().zip(res.iter_mut()) {
+            if let Ok(batch_res) = item_res {
+                for hook in self.hooks.iter() {
+                    let hook_res = match batch_res {
+                        BatchResult::Read(rows) => {
+                            hook.after_read(&item.query, headers, &item.args, rows)
+                                .await
+                        }
+                        BatchResult::Write(write_res) => {
+                            hook.after_write(&item.query, headers, &item.args, write_res)
+                                .await
+                        }
+                    };
+                    if let Err(e) = hook_res {
+                        *item_res = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(with_vetoes(vetoes, res))
+    }
+}
+
+/// Puts the hooks' vetoes back in among the results of the items that weren't vetoed, in order.
+///
+/// Arguments:
+///
+/// * `vetoes` - For each item, its veto, or `None` if it was run.
+/// * `res` - The results of the items that were run, in order.
+fn with_vetoes<T>(vetoes: Vec<Option<Error>>, res: Vec<Result<T, Error>>) -> Vec<Result<T, Error>> {
+    let mut res = res.into_iter();
+    vetoes
+        .into_iter()
+        .filter_map(|veto| match veto {
+            Some(e) => Some(Err(e)),
+            None => res.next(),
+        })
+        .collect()
+}
 
-pub type Args = HashMap<String, String>;
+#[axum::debug_handler]
+async fn openapi_endpoint(State(state): State<AppState>) -> impl IntoResponse {
+    // generated per request, so that it describes the queries of a reloaded spec
+    let served = state.db.served();
+    Json(openapi::openapi(
+        &served.queries,
+        &served.version,
+        &state.route_base,
+    ))
+}
 
 #[axum::debug_handler]
 async fn read_query_endpoint(
     Path(query): Path<String>,
-    Query(params): Query<Args>,
-    State(db): State<DB>,
+    params: Result<Query<HashMap<String, String>>, QueryRejection>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
-    match db.read_query(&query, &params, None).await {
+    let format = match requested_format(&headers) {
+        Ok(format) => format,
+        Err(e) => return e.into_response_with_details(state.debug),
+    };
+    let Query(params) = match params {
+        Ok(params) => params,
+        Err(e) => return Error::from(e).into_response_with_details(state.debug),
+    };
+    let params: Args = params
+        .into_iter()
+        .map(|(k, v)| (k, Value::String(v)))
+        .collect();
+    match state.read(&query, &headers, params, format).await {
         Ok(res) => Json(res).into_response(),
-        Err(e) => e.into_response(),
+        Err(e) => e.into_response_with_details(state.debug),
     }
 }
 
+/// The body of a write request: one set of arguments, or a list of them for a bulk write.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WriteRequest {
+    Args(Args),
+    Bulk(Vec<Args>),
+}
+
+/// Query string options for write requests.
+#[derive(Deserialize)]
+struct WriteOptions {
+    /// Whether one failed write rolls back a whole bulk write. Defaults to true.
+    #[serde(default = "all_or_nothing_default")]
+    all_or_nothing: bool,
+}
+
 #[axum::debug_handler]
 async fn write_query_endpoint(
     Path(query): Path<String>,
-    State(db): State<DB>,
-    Json(params): Json<Args>,
+    options: Result<Query<WriteOptions>, QueryRejection>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    req: Result<Json<WriteRequest>, JsonRejection>,
 ) -> impl IntoResponse {
-    db.write_query(&query, &params, None).await
+    let format = match requested_format(&headers) {
+        Ok(format) => format,
+        Err(e) => return e.into_response_with_details(state.debug),
+    };
+    let (Query(options), Json(req)) = match (options, req) {
+        (Ok(options), Ok(req)) => (options, req),
+        (Err(e), _) => return Error::from(e).into_response_with_details(state.debug),
+        (_, Err(e)) => return Error::from(e).into_response_with_details(state.debug),
+    };
+    let params = match req {
+        WriteRequest::Args(params) => params,
+        WriteRequest::Bulk(rows) => {
+            return match state
+                .bulk_write(&query, &headers, rows, options.all_or_nothing, format)
+                .await
+            {
+                Ok(res) => Json(results_to_json(res, state.debug)).into_response(),
+                Err(e) => e.into_response_with_details(state.debug),
+            };
+        }
+    };
+    match state.write(&query, &headers, params, format).await {
+        Ok(res) => Json(res).into_response(),
+        Err(e) => e.into_response_with_details(state.debug),
+    }
+}
+
+/// Renders the per-item results of a batch or bulk write as `{ "ok": true, "result": ... }` or `{ "ok": false, "error": ... }`.
+fn results_to_json<T: Serialize>(res: Vec<Result<T, Error>>, debug: bool) -> Vec<Value> {
+    res.into_iter()
+        .map(|item_res| match item_res {
+            Ok(item_res) => json!({ "ok": true, "result": item_res }),
+            Err(e) => json!({ "ok": false, "error": e.body(debug) }),
+        })
+        .collect()
+}
+
+/// The body of a batch request: either a bare list of items, or an object with options.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchRequest {
+    Items(Vec<BatchItem>),
+    Options {
+        items: Vec<BatchItem>,
+        /// Whether one failed item rolls back the whole batch. Defaults to true.
+        #[serde(default = "all_or_nothing_default")]
+        all_or_nothing: bool,
+    },
 }
-/// Internal core method that runs the Corolla server.
+
+fn all_or_nothing_default() -> bool {
+    true
+}
+
+#[axum::debug_handler]
+async fn batch_endpoint(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    req: Result<Json<BatchRequest>, JsonRejection>,
+) -> impl IntoResponse {
+    let format = match requested_format(&headers) {
+        Ok(format) => format,
+        Err(e) => return e.into_response_with_details(state.debug),
+    };
+    let Json(req) = match req {
+        Ok(req) => req,
+        Err(e) => return Error::from(e).into_response_with_details(state.debug),
+    };
+    let (items, all_or_nothing) = match req {
+        BatchRequest::Items(items) => (items, all_or_nothing_default()),
+        BatchRequest::Options {
+            items,
+            all_or_nothing,
+        } => (items, all_or_nothing),
+    };
+    match state.batch(&headers, items, all_or_nothing, format).await {
+        Ok(res) => Json(results_to_json(res, state.debug)).into_response(),
+        Err(e) => e.into_response_with_details(state.debug),
+    }
+}
+
+/// Builds the `axum::Router` that serves a spec's queries, so corolla can be mounted inside another axum app.
+/// The router has its state already, so it can be nested, layered and served like any other `Router`.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), corolla::Error> {
+/// let spec = corolla::read_spec("spec.json")?;
+/// let db = corolla::DB::from_spec("corolla.sqlite3", &spec, corolla::DbOptions::default()).await?;
+/// let app = axum::Router::new().merge(
+///     corolla::RouterBuilder::new(db)
+///         .route_base("/corolla")
+///         .build(),
+/// );
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+/// axum::serve(listener, app).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RouterBuilder {
+    db: DB,
+    route_base: String,
+    static_path: Option<String>,
+    debug: bool,
+    hooks: Vec<Box<dyn QueryHook>>,
+}
+
+impl RouterBuilder {
+    /// Arguments:
+    ///
+    /// * `db` - The database whose spec's queries are served.
+    pub fn new(db: DB) -> Self {
+        Self {
+            db,
+            route_base: String::new(),
+            static_path: None,
+            debug: false,
+            hooks: vec![],
+        }
+    }
+
+    /// Serves the endpoints under a base route. For instance, if `route_base == "/api"` then the `/read/:query` endpoint will be served under `/api/read/:query`.
+    /// Unlike nesting the built router, this also puts the base route in the paths of the OpenAPI document.
+    pub fn route_base(mut self, route_base: &str) -> Self {
+        self.route_base = route_base.to_owned();
+        self
+    }
+
+    /// Serves the files in a directory under `{route_base}/static`.
+    pub fn static_dir(mut self, static_path: &str) -> Self {
+        self.static_path = Some(static_path.to_owned());
+        self
+    }
+
+    /// Whether error responses include SQL error details. Defaults to false.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Runs a hook around every query. Hooks run in the order they were added.
+    pub fn hook(mut self, hook: impl QueryHook + 'static) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    /// Builds the router. It needs no state from the caller, so it can be merged or nested into any `Router`.
+    pub fn build(self) -> Router {
+        let route_base = &self.route_base;
+        let mut router = Router::new()
+            .route(
+                &format!("{route_base}/read/:query"),
+                get(read_query_endpoint),
+            )
+            .route(
+                &format!("{route_base}/write/:query"),
+                post(write_query_endpoint),
+            )
+            .route(&format!("{route_base}/batch"), post(batch_endpoint))
+            .route(&format!("{route_base}/openapi.json"), get(openapi_endpoint));
+        if let Some(static_path) = &self.static_path {
+            router =
+                router.nest_service(&format!("{route_base}/static"), ServeDir::new(static_path));
+        }
+        router.with_state(AppState {
+            route_base: Arc::from(route_base.as_str()),
+            db: self.db,
+            debug: self.debug,
+            hooks: Arc::new(self.hooks),
+        })
+    }
+}
+
+/// Checks a spec.json without serving it, by building it on throwaway in-memory databases.
+/// Runs `init`, prepares every query, runs every query example and checks the conversions, then fails with a report of every problem found.
+///
+/// Arguments:
+///
+/// * `spec_path` - Filepath to the spec.json.
+pub async fn self_check(spec_path: &str) -> Result<(), Error> {
+    let spec = read_spec(spec_path)?;
+    let problems = DB::self_check(&spec).await?;
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(Error::QueryChecks(problems)),
+    }
+}
+
+/// Runs the test cases in a spec.json's `tests` section, each on a fresh in-memory database, and fails with a report of every failed test case.
 ///
 /// Arguments:
 ///
-/// * `route_base` - The base HTTP route. For instance, if `route_base == "/api"` then the `/read/:query` endpoint will be served under `/api/read/:query`.
+/// * `spec_path` - Filepath to the spec.json.
+pub async fn run_tests(spec_path: &str) -> Result<(), Error> {
+    let spec = read_spec(spec_path)?;
+    info!("running {} tests", spec.tests.len());
+    let failures = testing::run_spec_tests(&spec).await?;
+    match failures.is_empty() {
+        true => Ok(()),
+        false => Err(Error::TestFailures(failures)),
+    }
+}
+
+/// What `migrate` does to the database.
+pub enum MigrateMode {
+    /// Run the conversions and init statements on the database.
+    Apply,
+    /// Only report which conversions would run.
+    Plan,
+    /// Run them on a temporary copy of the database, and report the copy's resulting schema.
+    DryRun,
+}
+
+/// Brings a database up to date with a spec.json without serving it, or rolls it back to a previous version,
+/// and writes which conversions run, and which are skipped, to stdout.
+///
+/// Arguments:
+///
+/// * `spec_path` - Filepath to the spec.json.
 /// * `db_path` - Filepath to the SQLite database.
-/// * `static_path` - Filepath to static file directory.
-/// * `port` - The port the server will listen on.
-/// * `init_statements` - A list of SQL statements that will be executed to initialize the database, in order.
-/// * `queries` - A lookup table of SQL queries.
-async fn serve(
-    route_base: &str,
-    port: i64,
+/// * `to` - The version to roll the database back to. Brings the database up to date with the spec if unset.
+/// * `mode` - Whether to change the database, or only report what would happen to it.
+/// * `options` - How to open the database.
+pub async fn migrate(
+    spec_path: &str,
     db_path: &str,
-    static_path: &str,
-    spec: &Spec,
+    to: Option<&InstanceVersion>,
+    mode: MigrateMode,
+    options: DbOptions,
 ) -> Result<(), Error> {
-    let addr = format!("0.0.0.0:{}", port);
-    let conn = DB::from_spec(db_path, &spec).await?;
-    info!("listening on {}", &addr);
-    let app = Router::new()
-        .route(
-            &format!("{route_base}/read/:query"),
-            get(read_query_endpoint),
-        )
-        .route(
-            &format!("{route_base}/write/:query"),
-            post(write_query_endpoint),
-        )
-        .nest_service(&format!("{route_base}/static"), ServeDir::new(static_path))
-        .with_state(conn);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app.into_make_service())
-        .await
-        .map_err(|_| Error::Server)?;
+    let spec = read_spec(spec_path)?;
+    let version = DB::read_version(db_path, options.busy_timeout).await?;
+    match to {
+        Some(target) => print!("{}", plan::rollback_report(&spec, version.as_ref(), target)),
+        None => print!("{}", plan::plan_report(&spec, version.as_ref())),
+    }
+    match mode {
+        MigrateMode::Plan => (),
+        MigrateMode::DryRun => {
+            let schema = DB::dry_run(db_path, &spec, to, options).await?;
+            match to {
+                Some(_) => println!("\nthe resulting schema:\n"),
+                None => println!("\nthe resulting schema, which passes the spec's query checks:\n"),
+            }
+            for sql in schema {
+                println!("{sql};");
+            }
+        }
+        MigrateMode::Apply => match to {
+            Some(target) => {
+                let v = DB::roll_back(db_path, &spec, target, options.busy_timeout).await?;
+                info!("{db_path} is at version {v}");
+            }
+            None => {
+                DB::from_spec(db_path, &spec, options)
+                    .await?
+                    .close()
+                    .await?;
+                info!("{db_path} is at version {}", spec.version);
+            }
+        },
+    }
     Ok(())
 }
-/// Run a Corolla web server according to server config and spec.json
+
+/// Writes the OpenAPI document describing a spec.json's endpoints, the same one served at `{route_base}/openapi.json`.
 ///
 /// Arguments:
 ///
-/// * `route_base` - The base HTTP route. For instance, if `route_base == "/api"` then the `/read/:query` endpoint will be served under `/api/read/:query`.
-/// * `port` - The port the server will listen on.
-/// * `db_path` - Filepath to the SQLite database.
-/// * `static_path` - Filepath to static file directory.
 /// * `spec_path` - Filepath to the spec.json.
-pub async fn run(
-    route_base: &str,
-    port: i64,
-    db_path: &str,
-    static_path: &str,
-    spec_path: &str,
-) -> Result<(), Error> {
-    let spec = read_spec(&spec_path)?;
-    serve(route_base, port, db_path, static_path, &spec).await?;
+/// * `route_base` - The base HTTP route the endpoints are served under.
+/// * `output` - Filepath to write the document to. Writes to stdout if unset.
+pub fn write_openapi(spec_path: &str, route_base: &str, output: Option<&str>) -> Result<(), Error> {
+    let spec = read_spec(spec_path)?;
+    let doc = openapi::openapi(&spec.queries, &spec.version, route_base);
+    match output {
+        Some(path) => serde_json::to_writer_pretty(File::create(path)?, &doc)?,
+        None => {
+            serde_json::to_writer_pretty(io::stdout(), &doc)?;
+            println!();
+        }
+    }
+    Ok(())
+}
+
+/// Writes a TypeScript client for the spec's queries.
+///
+/// Arguments:
+///
+/// * `spec_path` - Filepath of the spec.json file.
+/// * `output` - Filepath to write the module to; the module is written to stdout if `None`.
+pub fn write_typescript(spec_path: &str, output: Option<&str>) -> Result<(), Error> {
+    let spec = read_spec(spec_path)?;
+    let module = codegen::typescript(&spec)?;
+    match output {
+        Some(path) => File::create(path)?.write_all(module.as_bytes())?,
+        None => io::stdout().write_all(module.as_bytes())?,
+    }
+    Ok(())
+}
+
+/// How to run a Corolla web server.
+pub struct ServerConfig {
+    /// The base HTTP route. For instance, if `route_base == "/api"` then the `/read/:query` endpoint will be served under `/api/read/:query`.
+    pub route_base: String,
+    /// The port the server will listen on.
+    pub port: i64,
+    /// Filepath to the SQLite database.
+    pub db_path: String,
+    /// Filepath to static file directory.
+    pub static_path: String,
+    /// Filepath to the spec.json.
+    pub spec_path: String,
+    /// Whether error responses include SQL error details.
+    pub debug: bool,
+    /// How long a DB connection waits for a lock held by another process.
+    pub busy_timeout: Duration,
+    /// Whether to reload the spec when its file changes, as well as on SIGHUP.
+    pub watch_spec: bool,
+    /// How long to wait for requests in flight to finish when shutting down.
+    pub drain_timeout: Duration,
+    /// Filepath to write the server process's PID to, and delete when the server shuts down.
+    pub pid_file: Option<String>,
+    /// Whether to serve a spec older than the database, and record its version in the database.
+    pub allow_downgrade: bool,
+}
+
+/// Returns a future that resolves when the process gets a SIGINT or a SIGTERM.
+#[cfg(unix)]
+fn shutdown_signal() -> io::Result<impl Future<Output = ()>> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => info!("got SIGINT, shutting down"),
+            _ = terminate.recv() => info!("got SIGTERM, shutting down"),
+        }
+    })
+}
+
+/// Returns a future that resolves when the process gets a Ctrl-C, the only shutdown signal outside Unix.
+#[cfg(not(unix))]
+fn shutdown_signal() -> io::Result<impl Future<Output = ()>> {
+    Ok(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("got Ctrl-C, shutting down");
+        }
+    })
+}
+
+/// The process's SIGHUP signals, which reload the spec.
+#[cfg(unix)]
+struct Hangups(Signal);
+
+/// Outside Unix there are no SIGHUP signals, and only watching the spec's file reloads it.
+#[cfg(not(unix))]
+struct Hangups;
+
+#[cfg(unix)]
+impl Hangups {
+    fn new() -> io::Result<Self> {
+        Ok(Self(signal(SignalKind::hangup())?))
+    }
+
+    /// Resolves at the next SIGHUP.
+    async fn recv(&mut self) {
+        self.0.recv().await;
+    }
+}
+
+#[cfg(not(unix))]
+impl Hangups {
+    fn new() -> io::Result<Self> {
+        Ok(Self)
+    }
+
+    /// Never resolves.
+    async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+/// Reloads the spec whenever the process gets a SIGHUP, and whenever the spec's file changes if `watch` is set.
+/// A spec that fails to load or to check against the database is logged, and the old spec keeps being served.
+///
+/// Arguments:
+///
+/// * `db` - The database serving the spec.
+/// * `spec_path` - Filepath to the spec.json.
+/// * `watch` - Whether to poll the spec's file for changes.
+/// * `hangups` - The process's SIGHUP signals.
+async fn reload_spec(db: DB, spec_path: String, watch: bool, mut hangups: Hangups) {
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut last_modified = modified(&spec_path);
+    let mut poll = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = hangups.recv() => info!("got SIGHUP, reloading {spec_path}"),
+            _ = poll.tick(), if watch => {
+                let now_modified = modified(&spec_path);
+                if now_modified == last_modified {
+                    continue;
+                }
+                last_modified = now_modified;
+                info!("{spec_path} changed, reloading it");
+            }
+        }
+        let res = match read_spec(&spec_path) {
+            Ok(spec) => db.reload(&spec).await,
+            Err(e) => Err(e.into()),
+        };
+        match res {
+            Ok(()) => info!("reloaded {spec_path}"),
+            Err(e) => error!("could not reload {spec_path}, still serving the old spec: {e}"),
+        }
+    }
+}
+
+/// Run a Corolla web server according to server config and spec.json
+///
+/// Arguments:
+///
+/// * `config` - How to run the server.
+pub async fn run(config: &ServerConfig) -> Result<(), Error> {
+    if let Some(path) = &config.pid_file {
+        fs::write(path, process::id().to_string())?;
+    }
+    let res = serve(config).await;
+    if let Some(path) = &config.pid_file {
+        // a failure to clean up doesn't hide how serving went
+        if let Err(e) = fs::remove_file(path) {
+            error!("could not remove {path}: {e}");
+        }
+    }
+    res
+}
+
+/// Serves the spec until the process gets a SIGINT or a SIGTERM, then stops taking new connections,
+/// waits up to the drain timeout for requests in flight, and closes the database.
+async fn serve(config: &ServerConfig) -> Result<(), Error> {
+    let spec = read_spec(&config.spec_path)?;
+    let addr = format!("0.0.0.0:{}", config.port);
+    let options = DbOptions::default()
+        .busy_timeout(config.busy_timeout)
+        .allow_downgrade(config.allow_downgrade);
+    let db = DB::from_spec(&config.db_path, &spec, options).await?;
+    tokio::spawn(reload_spec(
+        db.clone(),
+        config.spec_path.clone(),
+        config.watch_spec,
+        Hangups::new()?,
+    ));
+    info!("listening on {}", &addr);
+    let app = RouterBuilder::new(db.clone())
+        .route_base(&config.route_base)
+        .static_dir(&config.static_path)
+        .debug(config.debug)
+        .build();
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    let stopping = Arc::new(Notify::new());
+    let shutdown = shutdown_signal()?;
+    let server = axum::serve(listener, app.into_make_service()).with_graceful_shutdown({
+        let stopping = stopping.clone();
+        async move {
+            shutdown.await;
+            stopping.notify_one();
+        }
+    });
+    tokio::select! {
+        res = server => res.map_err(|_| Error::Server)?,
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(config.drain_timeout).await;
+        } => warn!("requests still in flight after {:?}, shutting down anyway", config.drain_timeout),
+    }
+    // a write still in flight finishes before the database closes
+    db.close().await?;
+    info!("shut down");
     Ok(())
 }
@@ -0,0 +1,341 @@
+/// This file contains methods for describing a spec's HTTP API as an [OpenAPI 3.1](https://spec.openapis.org/oas/v3.1.0) document.
+use super::FORMAT_HEADER;
+use corolla_spec::{Arg, ArgType, InstanceVersion, Queries};
+use serde_json::{json, Map, Value};
+
+/// The JSON schema of an argument, from its declared type and constraints.
+fn arg_schema(arg: &Arg) -> Value {
+    let mut schema = match arg.r#type {
+        Some(ArgType::Integer) => json!({ "type": "integer" }),
+        Some(ArgType::Real) => json!({ "type": "number" }),
+        Some(ArgType::Text) => json!({ "type": "string" }),
+        Some(ArgType::Boolean) => json!({ "type": "boolean" }),
+        Some(ArgType::Blob) => json!({ "type": "string", "contentEncoding": "base64" }),
+        Some(ArgType::Date) => json!({ "type": "string", "format": "date" }),
+        // JSON args and untyped args take any value
+        Some(ArgType::Json) | None => json!({}),
+    };
+    if arg.optional && !arg.default.is_null() {
+        schema["default"] = arg.default.clone();
+    }
+    if let Some(regex) = &arg.regex {
+        schema["pattern"] = json!(regex);
+    }
+    if let Some(allowed) = &arg.r#enum {
+        schema["enum"] = json!(allowed);
+    }
+    if let Some(min) = arg.min {
+        schema["minimum"] = json!(min);
+    }
+    if let Some(max) = arg.max {
+        schema["maximum"] = json!(max);
+    }
+    if let Some(max_length) = arg.max_length {
+        schema["maxLength"] = json!(max_length);
+    }
+    schema
+}
+
+/// The JSON schema of query results: a header row holding the column names, then one row per result.
+fn rows_schema(cols: &[String]) -> Value {
+    json!({
+        "type": "array",
+        "prefixItems": [{
+            "type": "array",
+            "prefixItems": cols.iter().map(|c| json!({ "const": c })).collect::<Vec<_>>(),
+            "items": false
+        }],
+        "items": {
+            "type": "array",
+            "minItems": cols.len(),
+            "maxItems": cols.len(),
+            "items": { "$ref": "#/components/schemas/Value" }
+        }
+    })
+}
+
+/// The JSON schema of a write request's arguments.
+fn args_schema(args: &[Arg]) -> Value {
+    let properties: Map<String, Value> = args
+        .iter()
+        .map(|a| (a.name.clone(), arg_schema(a)))
+        .collect();
+    let required: Vec<&str> = args
+        .iter()
+        .filter(|a| !a.optional)
+        .map(|a| a.name.as_str())
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false
+    })
+}
+
+/// The error response every endpoint can return.
+fn error_response() -> Value {
+    json!({
+        "description": "The request failed.",
+        "content": {
+            "application/json": { "schema": { "$ref": "#/components/schemas/Error" } }
+        }
+    })
+}
+
+/// Describes a spec's HTTP API as an OpenAPI document.
+///
+/// Arguments:
+///
+/// * `queries` - The spec's queries.
+/// * `version` - The spec's version.
+/// * `route_base` - The base HTTP route the queries are served under.
+pub fn openapi(queries: &Queries, version: &InstanceVersion, route_base: &str) -> Value {
+    let mut paths = Map::new();
+    let mut names: Vec<&String> = queries.read.keys().collect();
+    names.sort();
+    for name in names {
+        let query = &queries.read[name];
+        let mut parameters: Vec<Value> = query
+            .args
+            .iter()
+            .map(|a| {
+                json!({
+                    "name": a.name,
+                    "in": "query",
+                    "required": !a.optional,
+                    "schema": arg_schema(a)
+                })
+            })
+            .collect();
+        parameters.push(json!({ "$ref": "#/components/parameters/Format" }));
+        paths.insert(
+            format!("{route_base}/read/{name}"),
+            json!({
+                "get": {
+                    "operationId": format!("read_{name}"),
+                    "parameters": parameters,
+                    "responses": {
+                        "200": {
+                            "description": "The query's results.",
+                            "content": { "application/json": { "schema": rows_schema(&query.cols) } }
+                        },
+                        "default": error_response()
+                    }
+                }
+            }),
+        );
+    }
+    let mut names: Vec<&String> = queries.write.keys().collect();
+    names.sort();
+    for name in names {
+        let query = &queries.write[name];
+        let args = args_schema(&query.args);
+        let mut result = json!({
+            "type": "object",
+            "properties": {
+                "rows_affected": { "type": "integer" },
+                "last_insert_rowid": { "type": ["integer", "null"] }
+            },
+            "required": ["rows_affected", "last_insert_rowid"]
+        });
+        if let Some(cols) = &query.cols {
+            result["properties"]["rows"] = rows_schema(cols);
+        }
+        paths.insert(
+            format!("{route_base}/write/{name}"),
+            json!({
+                "post": {
+                    "operationId": format!("write_{name}"),
+                    "parameters": [
+                        { "$ref": "#/components/parameters/Format" },
+                        { "$ref": "#/components/parameters/AllOrNothing" }
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "description": "The query's arguments, or a list of them for a bulk write.",
+                        "content": {
+                            "application/json": {
+                                "schema": { "oneOf": [args, { "type": "array", "items": args }] }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": {
+                            "description": "What the write changed, or one result per set of arguments for a bulk write.",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "oneOf": [
+                                            result,
+                                            { "type": "array", "items": { "$ref": "#/components/schemas/ItemResult" } }
+                                        ]
+                                    }
+                                }
+                            }
+                        },
+                        "default": error_response()
+                    }
+                }
+            }),
+        );
+    }
+    paths.insert(
+        format!("{route_base}/batch"),
+        json!({
+            "post": {
+                "operationId": "batch",
+                "parameters": [{ "$ref": "#/components/parameters/Format" }],
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "oneOf": [
+                                    { "type": "array", "items": { "$ref": "#/components/schemas/BatchItem" } },
+                                    {
+                                        "type": "object",
+                                        "properties": {
+                                            "items": { "type": "array", "items": { "$ref": "#/components/schemas/BatchItem" } },
+                                            "all_or_nothing": { "type": "boolean", "default": true }
+                                        },
+                                        "required": ["items"]
+                                    }
+                                ]
+                            }
+                        }
+                    }
+                },
+                "responses": {
+                    "200": {
+                        "description": "One result per query.",
+                        "content": {
+                            "application/json": {
+                                "schema": { "type": "array", "items": { "$ref": "#/components/schemas/ItemResult" } }
+                            }
+                        }
+                    },
+                    "default": error_response()
+                }
+            }
+        }),
+    );
+    json!({
+        "openapi": "3.1.0",
+        "info": { "title": "corolla", "version": version.to_string() },
+        "paths": paths,
+        "components": {
+            "parameters": {
+                "Format": {
+                    "name": FORMAT_HEADER,
+                    "in": "header",
+                    "required": false,
+                    "description": "Overrides how result values are rendered.",
+                    "schema": { "enum": ["typed", "strings"] }
+                },
+                "AllOrNothing": {
+                    "name": "all_or_nothing",
+                    "in": "query",
+                    "required": false,
+                    "description": "Whether one failed write rolls back a whole bulk write.",
+                    "schema": { "type": "boolean", "default": true }
+                }
+            },
+            "schemas": {
+                "Value": { "type": ["string", "number", "boolean", "null"] },
+                "Error": {
+                    "type": "object",
+                    "properties": {
+                        "code": { "type": "string" },
+                        "message": { "type": "string" },
+                        "details": {}
+                    },
+                    "required": ["code", "message"]
+                },
+                "BatchItem": {
+                    "type": "object",
+                    "properties": {
+                        "type": { "enum": ["read", "write"] },
+                        "query": { "type": "string" },
+                        "args": { "type": "object" }
+                    },
+                    "required": ["type", "query"]
+                },
+                "ItemResult": {
+                    "oneOf": [
+                        {
+                            "type": "object",
+                            "properties": { "ok": { "const": true }, "result": {} },
+                            "required": ["ok", "result"]
+                        },
+                        {
+                            "type": "object",
+                            "properties": {
+                                "ok": { "const": false },
+                                "error": { "$ref": "#/components/schemas/Error" }
+                            },
+                            "required": ["ok", "error"]
+                        }
+                    ]
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use corolla_spec::Spec;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    /// queries become paths, with schemas from their args and cols
+    fn openapi_paths() {
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 2, 3],
+            "init": [],
+            "queries": {
+                "read": {
+                    "people": {
+                        "sql_template": "select name, age from people where age >= ?;",
+                        "args": [{ "name": "min_age", "type": "integer", "min": 0, "optional": true, "default": 0 }],
+                        "cols": ["name", "age"]
+                    }
+                },
+                "write": {
+                    "add": {
+                        "sql_template": "insert into people values (?, ?);",
+                        "args": ["name", { "name": "age", "type": "integer" }]
+                    }
+                }
+            },
+            "conversions": []
+        }))
+        .unwrap();
+        let doc = openapi(&spec.queries, &spec.version, "/api");
+        assert_eq!(doc["info"]["version"], "1.2.3");
+        let read = &doc["paths"]["/api/read/people"]["get"];
+        assert_eq!(
+            read["parameters"][0],
+            json!({
+                "name": "min_age",
+                "in": "query",
+                "required": false,
+                "schema": { "type": "integer", "default": 0, "minimum": 0.0 }
+            })
+        );
+        let rows = &read["responses"]["200"]["content"]["application/json"]["schema"];
+        assert_eq!(
+            rows["prefixItems"][0]["prefixItems"],
+            json!([{ "const": "name" }, { "const": "age" }])
+        );
+        assert_eq!(rows["items"]["maxItems"], 2);
+        let write = &doc["paths"]["/api/write/add"]["post"];
+        let args = &write["requestBody"]["content"]["application/json"]["schema"]["oneOf"][0];
+        assert_eq!(args["required"], json!(["name", "age"]));
+        assert_eq!(args["properties"]["name"], json!({}));
+        assert_eq!(args["properties"]["age"], json!({ "type": "integer" }));
+    }
+}
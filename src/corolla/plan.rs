@@ -0,0 +1,247 @@
+/// This file contains methods for working out, and reporting, what bringing a database up to date with a spec would do.
+use corolla_spec::{InstanceVersion, Spec};
+use std::{cmp, fmt::Write};
+
+/// Whether a conversion would run on a database, and the database version before and after it.
+#[derive(Debug, PartialEq)]
+pub struct PlannedConversion {
+    /// The conversion's position in the spec's `conversions`.
+    pub index: usize,
+    /// Whether the database version is at or below the conversion's `max_version` when it's reached.
+    pub runs: bool,
+    pub from_version: InstanceVersion,
+    pub to_version: InstanceVersion,
+}
+
+/// Works out which of a spec's conversions run on a database at the given version, in order, and which are skipped.
+pub fn plan_conversions(spec: &Spec, version: &InstanceVersion) -> Vec<PlannedConversion> {
+    let mut v = version.clone();
+    spec.conversions
+        .iter()
+        .enumerate()
+        .map(|(index, conversion)| {
+            let from_version = v.clone();
+            let runs = v <= conversion.max_version;
+            if runs {
+                v = conversion.new_version.clone();
+            }
+            PlannedConversion {
+                index,
+                runs,
+                from_version,
+                to_version: v.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Works out which conversions a database at the given version has to undo to roll back to the target version, newest first,
+/// and the version it ends up at. That's the target, unless the last conversion undone needs a lower version to run again.
+///
+/// Arguments:
+///
+/// * `spec` - The spec holding the conversions applied to the database.
+/// * `version` - The database's instance version.
+/// * `target` - The version to roll back to.
+pub fn plan_rollback(
+    spec: &Spec,
+    version: &InstanceVersion,
+    target: &InstanceVersion,
+) -> (Vec<PlannedConversion>, InstanceVersion) {
+    let mut v = version.clone();
+    let mut steps = vec![];
+    for (index, conversion) in spec.conversions.iter().enumerate().rev() {
+        if conversion.new_version <= v && conversion.new_version > *target {
+            steps.push(PlannedConversion {
+                index,
+                runs: true,
+                from_version: v.clone(),
+                to_version: conversion.max_version.clone(),
+            });
+            v = conversion.max_version.clone();
+        }
+    }
+    let end = cmp::min(v, target.clone());
+    (steps, end)
+}
+
+/// Describes which of a spec's conversions would run on a database, and which would be skipped, one line each.
+///
+/// Arguments:
+///
+/// * `spec` - The spec whose conversions are planned.
+/// * `version` - The database's instance version, or `None` if corolla has never initialized it.
+pub fn plan_report(spec: &Spec, version: Option<&InstanceVersion>) -> String {
+    let mut report = String::new();
+    let version = match version {
+        Some(v) => v,
+        None => {
+            return format!(
+                "the database is not initialized: no conversions run, and init creates it at version {}\n",
+                spec.version
+            );
+        }
+    };
+    // writing to a String can't fail
+    let _ = writeln!(report, "the database is at version {version}");
+    if *version > spec.version {
+        let _ = writeln!(
+            report,
+            "the database is newer than the spec's version {}, so it won't be opened without --allow-downgrade",
+            spec.version
+        );
+    }
+    for step in plan_conversions(spec, version) {
+        let i = step.index;
+        let _ = match step.runs {
+            true => writeln!(
+                report,
+                "conversion {i} runs: {} -> {}",
+                step.from_version, step.to_version
+            ),
+            false => writeln!(
+                report,
+                "conversion {i} is skipped: {} is past its max_version {}",
+                step.from_version, spec.conversions[i].max_version
+            ),
+        };
+    }
+    let _ = writeln!(
+        report,
+        "then init runs, and the database is set to version {}",
+        spec.version
+    );
+    report
+}
+
+/// Describes which conversions a database would undo to roll back to the target version, and whether any of them can't be undone.
+///
+/// Arguments:
+///
+/// * `spec` - The spec holding the conversions applied to the database.
+/// * `version` - The database's instance version, or `None` if corolla has never initialized it.
+/// * `target` - The version to roll back to.
+pub fn rollback_report(
+    spec: &Spec,
+    version: Option<&InstanceVersion>,
+    target: &InstanceVersion,
+) -> String {
+    let version = match version {
+        Some(v) => v,
+        None => {
+            return "the database is not initialized, so there is nothing to roll back\n".to_owned()
+        }
+    };
+    if target > version {
+        return format!("the database is at version {version}, below {target}, so it can't be rolled back to it\n");
+    }
+    let mut report = String::new();
+    let _ = writeln!(report, "the database is at version {version}");
+    let (steps, end) = plan_rollback(spec, version, target);
+    let refused = steps
+        .iter()
+        .any(|step| spec.conversions[step.index].down_queries.is_none());
+    for step in steps {
+        let i = step.index;
+        let _ = match spec.conversions[i].down_queries {
+            Some(_) => writeln!(
+                report,
+                "conversion {i} is undone: {} -> {}",
+                step.from_version, step.to_version
+            ),
+            None => writeln!(
+                report,
+                "conversion {i} can't be undone: it has no down_queries"
+            ),
+        };
+    }
+    let _ = match (refused, end == *target) {
+        (true, _) => writeln!(report, "so the database can't be rolled back to {target}"),
+        (false, false) => writeln!(
+            report,
+            "which goes past {target}, to {end}, so the database can't be rolled back to {target}"
+        ),
+        (false, true) => writeln!(report, "then the database is set to version {end}"),
+    };
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    /// conversions run while the database version is at or below their max_version, and are undone newest first
+    fn plans() {
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 2, 0],
+            "init": [],
+            "queries": { "read": {}, "write": {} },
+            "conversions": [
+                { "max_version": [1, 0, 0], "new_version": [1, 1, 0], "queries": [], "down_queries": [] },
+                { "max_version": [1, 1, 0], "new_version": [1, 2, 0], "queries": [] }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(
+            plan_report(&spec, Some(&InstanceVersion::from([1, 1, 0]))),
+            "the database is at version 1.1.0\n\
+             conversion 0 is skipped: 1.1.0 is past its max_version 1.0.0\n\
+             conversion 1 runs: 1.1.0 -> 1.2.0\n\
+             then init runs, and the database is set to version 1.2.0\n"
+        );
+        assert_eq!(
+            plan_report(&spec, Some(&InstanceVersion::from([1, 0, 0]))),
+            "the database is at version 1.0.0\n\
+             conversion 0 runs: 1.0.0 -> 1.1.0\n\
+             conversion 1 runs: 1.1.0 -> 1.2.0\n\
+             then init runs, and the database is set to version 1.2.0\n"
+        );
+        assert_eq!(
+            rollback_report(
+                &spec,
+                Some(&InstanceVersion::from([1, 2, 0])),
+                &InstanceVersion::from([1, 0, 5])
+            ),
+            "the database is at version 1.2.0\n\
+             conversion 1 can't be undone: it has no down_queries\n\
+             conversion 0 is undone: 1.1.0 -> 1.0.0\n\
+             so the database can't be rolled back to 1.0.5\n"
+        );
+        assert_eq!(
+            rollback_report(
+                &spec,
+                Some(&InstanceVersion::from([1, 1, 0])),
+                &InstanceVersion::from([1, 0, 5])
+            ),
+            "the database is at version 1.1.0\n\
+             conversion 0 is undone: 1.1.0 -> 1.0.0\n\
+             which goes past 1.0.5, to 1.0.0, so the database can't be rolled back to 1.0.5\n"
+        );
+        assert_eq!(
+            rollback_report(
+                &spec,
+                Some(&InstanceVersion::from([1, 1, 0])),
+                &InstanceVersion::from([1, 0, 0])
+            ),
+            "the database is at version 1.1.0\n\
+             conversion 0 is undone: 1.1.0 -> 1.0.0\n\
+             then the database is set to version 1.0.0\n"
+        );
+        assert_eq!(
+            plan_report(&spec, Some(&InstanceVersion::from([1, 3, 0]))),
+            "the database is at version 1.3.0\n\
+             the database is newer than the spec's version 1.2.0, so it won't be opened without --allow-downgrade\n\
+             conversion 0 is skipped: 1.3.0 is past its max_version 1.0.0\n\
+             conversion 1 is skipped: 1.3.0 is past its max_version 1.1.0\n\
+             then init runs, and the database is set to version 1.2.0\n"
+        );
+        assert_eq!(
+            plan_report(&spec, None),
+            "the database is not initialized: no conversions run, and init creates it at version 1.2.0\n"
+        );
+    }
+}
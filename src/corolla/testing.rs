@@ -0,0 +1,153 @@
+/// This file contains the runner for the test cases declared in a spec's `tests` section.
+use super::{db::DB, error::Error};
+use corolla_spec::{BatchItemType, Spec, SpecTest, TestStep};
+use log::info;
+use serde_json::{json, Value};
+
+/// Runs one step of a test case, and returns the rows it returned without their header row.
+async fn run_step(db: &DB, step: &TestStep) -> Result<Vec<Vec<Value>>, Error> {
+    let call = &step.call;
+    let rows = match call.r#type {
+        BatchItemType::Read => db.read_query(&call.query, &call.args, None).await?,
+        BatchItemType::Write => db
+            .write_query(&call.query, &call.args, None)
+            .await?
+            .rows
+            .unwrap_or_default(),
+    };
+    Ok(rows.into_iter().skip(1).collect())
+}
+
+/// Runs the steps of a test case in order, and describes the first one that doesn't have its expected outcome.
+async fn run_test(db: &DB, test: &SpecTest) -> Result<(), String> {
+    for (i, step) in test.steps.iter().enumerate() {
+        let step_name = format!("step {i} ({} {})", step.call.query, json!(step.call.args));
+        match (run_step(db, step).await, &step.error) {
+            (Ok(_), Some(code)) => {
+                return Err(format!(
+                    "{step_name} should have failed with {code}, but succeeded"
+                ))
+            }
+            (Err(e), Some(code)) if e.code() != code => {
+                return Err(format!(
+                    "{step_name} should have failed with {code}, but failed with {}: {e}",
+                    e.code()
+                ))
+            }
+            (Err(e), None) => return Err(format!("{step_name} failed: {e}")),
+            (Ok(rows), None) => {
+                if let Some(expected) = &step.rows {
+                    if &rows != expected {
+                        return Err(format!(
+                            "{step_name} should have returned {} but returned {}",
+                            Value::from(expected.clone()),
+                            Value::from(rows)
+                        ));
+                    }
+                }
+            }
+            (Err(_), Some(_)) => (),
+        }
+    }
+    Ok(())
+}
+
+/// Runs a spec's test cases, each on its own fresh in-memory database built by `init` the way a new live database is, and returns a description of every failed test case.
+///
+/// Arguments:
+///
+/// * `spec` - The spec whose test cases are run.
+pub async fn run_spec_tests(spec: &Spec) -> Result<Vec<String>, Error> {
+    let mut failures = vec![];
+    for test in &spec.tests {
+        let db = DB::in_memory(spec).await?;
+        match run_test(&db, test).await {
+            Ok(()) => info!("test {} passed", test.name),
+            Err(failure) => failures.push(format!("{}: {failure}", test.name)),
+        }
+    }
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    /// test cases check the rows and error codes of each step, and stop at the first unexpected outcome
+    async fn spec_tests() {
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 0],
+            "init": ["create table t (x text unique);"],
+            "queries": {
+                "read": {
+                    "read01": { "sql_template": "select x from t;", "args": [], "cols": ["x"] }
+                },
+                "write": {
+                    "write01": { "sql_template": "insert into t values (?);", "args": ["x"] }
+                }
+            },
+            "conversions": [],
+            "tests": [
+                {
+                    "name": "inserts",
+                    "steps": [
+                        { "type": "write", "query": "write01", "args": { "x": "a" } },
+                        { "type": "write", "query": "write01", "args": { "x": "a" }, "error": "constraint_violation" },
+                        { "type": "read", "query": "read01", "rows": [["a"]] }
+                    ]
+                },
+                {
+                    "name": "fresh database",
+                    "steps": [
+                        { "type": "read", "query": "read01", "rows": [["a"]] },
+                        { "type": "read", "query": "read01", "args": { "y": 1 } }
+                    ]
+                },
+                {
+                    "name": "wrong error",
+                    "steps": [
+                        { "type": "read", "query": "read01", "args": { "y": 1 }, "error": "validation_failed" }
+                    ]
+                }
+            ]
+        }))
+        .unwrap();
+        let failures = run_spec_tests(&spec).await.unwrap();
+        assert_eq!(
+            failures,
+            vec![
+                "fresh database: step 0 (read01 {}) should have returned [[\"a\"]] but returned []",
+                "wrong error: step 0 (read01 {\"y\":1}) should have failed with validation_failed, but failed with invalid_args: invalid args; unexpected: y"
+            ]
+        );
+    }
+    #[tokio::test]
+    /// test cases run on a database built by `init` alone, so a conversion that alters a table from `init` doesn't stop them
+    async fn spec_tests_skip_conversions() {
+        let spec: Spec = serde_json::from_value(json!({
+            "spec_version": [1, 0, 0],
+            "version": [1, 0, 1],
+            "init": ["create table t (x text unique, y text);"],
+            "queries": {
+                "read": {
+                    "read01": { "sql_template": "select x, y from t;", "args": [], "cols": ["x", "y"] }
+                },
+                "write": {}
+            },
+            "conversions": [{
+                "max_version": [1, 0, 0],
+                "new_version": [1, 0, 1],
+                "queries": ["alter table t add column y text;", "insert into t values ('seed', null);"]
+            }],
+            "tests": [{
+                "name": "empty",
+                "steps": [{ "type": "read", "query": "read01", "rows": [] }]
+            }]
+        }))
+        .unwrap();
+        assert_eq!(run_spec_tests(&spec).await.unwrap(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,174 @@
+/// This file contains methods for converting between SQLite values and JSON values.
+use super::error::Error;
+use corolla_spec::{BlobEncoding, ResultFormat, SqlValue};
+use serde_json::Value;
+use sqlx::{
+    query::Query,
+    sqlite::{SqliteArguments, SqliteRow},
+    Column, Row, Sqlite, TypeInfo, ValueRef,
+};
+
+/// A SQLite statement with its arguments bound so far.
+pub type SqliteQuery<'q> = Query<'q, Sqlite, SqliteArguments<'q>>;
+
+/// Parses a result format's name, e.g. from the `x-corolla-format` header.
+pub fn parse_format(name: &str) -> Result<ResultFormat, Error> {
+    match name {
+        "typed" => Ok(ResultFormat::Typed),
+        "strings" => Ok(ResultFormat::Strings),
+        _ => Err(Error::UnknownResultFormat(name.to_owned())),
+    }
+}
+
+/// Converts a SQLite row into a list of JSON values, one per column.
+///
+/// Arguments:
+///
+/// * `row` - The row to convert.
+/// * `format` - Whether values keep their SQLite types or are all rendered as strings.
+/// * `blobs` - How BLOB values are encoded.
+pub fn row_to_json(
+    row: &SqliteRow,
+    format: ResultFormat,
+    blobs: BlobEncoding,
+) -> Result<Vec<Value>, Error> {
+    let mut res = Vec::<Value>::with_capacity(row.len());
+    for c in 0..row.len() {
+        let raw = row.try_get_raw(c)?;
+        if raw.is_null() {
+            res.push(match format {
+                ResultFormat::Typed => Value::Null,
+                ResultFormat::Strings => Value::String(String::new()),
+            });
+            continue;
+        }
+        // the value's own storage class, not the column's declared type
+        let storage_class = raw.type_info().name().to_owned();
+        let val = match storage_class.as_str() {
+            "INTEGER" => {
+                let i = row.try_get_unchecked::<i64, usize>(c)?;
+                match format {
+                    ResultFormat::Typed if row.column(c).type_info().name() == "BOOLEAN" => {
+                        Value::Bool(i != 0)
+                    }
+                    ResultFormat::Typed => Value::from(i),
+                    ResultFormat::Strings => Value::String(i.to_string()),
+                }
+            }
+            "REAL" => {
+                let f = row.try_get_unchecked::<f64, usize>(c)?;
+                match format {
+                    // NaN and infinities have no JSON representation
+                    ResultFormat::Typed => serde_json::Number::from_f64(f)
+                        .map(Value::Number)
+                        .unwrap_or(Value::Null),
+                    ResultFormat::Strings => Value::String(f.to_string()),
+                }
+            }
+            "BLOB" => Value::String(blobs.encode(&row.try_get_unchecked::<Vec<u8>, usize>(c)?)),
+            _ => Value::String(row.try_get_unchecked::<String, usize>(c)?),
+        };
+        res.push(val);
+    }
+    Ok(res)
+}
+
+/// Binds a value to the next parameter of a statement.
+pub fn bind(val: SqlValue, statement: SqliteQuery<'_>) -> SqliteQuery<'_> {
+    match val {
+        SqlValue::Null => statement.bind(None::<String>),
+        SqlValue::Integer(i) => statement.bind(i),
+        SqlValue::Real(f) => statement.bind(f),
+        SqlValue::Text(s) => statement.bind(s),
+        SqlValue::Blob(b) => statement.bind(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use sqlx::{Connection, SqliteConnection};
+
+    async fn select(sql: &str, format: ResultFormat, blobs: BlobEncoding) -> Vec<Value> {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("create table t (i integer, r real, s text, b blob, f boolean, n text);")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        sqlx::query("insert into t values (42, 1.5, 'hi', x'cafe', 1, null);")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        let row = sqlx::query(sql).fetch_one(&mut conn).await.unwrap();
+        row_to_json(&row, format, blobs).unwrap()
+    }
+
+    #[tokio::test]
+    /// values keep their SQLite types
+    async fn typed_values() {
+        let res = select(
+            "select i, r, s, b, f, n from t;",
+            ResultFormat::Typed,
+            BlobEncoding::Base64,
+        )
+        .await;
+        assert_eq!(
+            res,
+            vec![
+                json!(42),
+                json!(1.5),
+                json!("hi"),
+                json!("yv4="),
+                json!(true),
+                json!(null)
+            ]
+        );
+        let res = select("select b from t;", ResultFormat::Typed, BlobEncoding::Hex).await;
+        assert_eq!(res, vec![json!("cafe")]);
+    }
+
+    #[tokio::test]
+    /// JSON values are bound with matching SQLite types
+    async fn bind_values() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        let mut statement = sqlx::query("select typeof(?), typeof(?), typeof(?), typeof(?), ?;");
+        for val in [json!(null), json!(1), json!(1.5), json!("hi"), json!(true)] {
+            statement = bind(SqlValue::from(&val), statement);
+        }
+        let row = statement.fetch_one(&mut conn).await.unwrap();
+        let res = row_to_json(&row, ResultFormat::Typed, BlobEncoding::Base64).unwrap();
+        assert_eq!(
+            res,
+            vec![
+                json!("null"),
+                json!("integer"),
+                json!("real"),
+                json!("text"),
+                json!(1)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    /// the legacy format renders every value as a string
+    async fn string_values() {
+        let res = select(
+            "select i, r, s, f, n from t;",
+            ResultFormat::Strings,
+            BlobEncoding::Base64,
+        )
+        .await;
+        assert_eq!(
+            res,
+            vec![
+                json!("42"),
+                json!("1.5"),
+                json!("hi"),
+                json!("1"),
+                json!("")
+            ]
+        );
+    }
+}
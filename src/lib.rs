@@ -0,0 +1,22 @@
+//! corolla, "your liteweight backend": a SQLite web server whose queries are declared in a spec.json.
+//!
+//! The `corolla` binary serves a spec with [`run`]. To mount corolla inside your own axum app instead,
+//! open a [`DB`] from a [`Spec`] and build its routes with [`RouterBuilder`].
+//!
+//! While corolla is `0.x`, a new minor version may still change what's exported here.
+mod corolla;
+
+pub use async_trait::async_trait;
+
+pub use corolla::{
+    db::{DbOptions, Served, WriteResult, DB},
+    error::{Error, ErrorBody},
+    hook::QueryHook,
+    migrate, run, run_tests, self_check, write_openapi, write_typescript, Args, MigrateMode,
+    RouterBuilder, ServerConfig,
+};
+pub use corolla_spec::{
+    read_spec, Arg, ArgType, BatchItem, BatchItemType, BlobEncoding, Conversion, InstanceVersion,
+    ParseVersionError, Pattern, Queries, ReadQuery, ResultFormat, Spec, SpecError, SpecTest,
+    SpecVersion, SqlValue, TestStep, Version, WriteQuery, WriteStatement,
+};
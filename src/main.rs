@@ -1,16 +1,26 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use log::{error, info, LevelFilter};
-use std::{env, fs::File, io::Write, process};
-
-mod corolla;
+use std::{env, process, time::Duration};
 
 /// "your liteweight backend"
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Open a database that a newer spec has converted, and record the older spec's version in it
+    #[arg(long, global = true)]
+    allow_downgrade: bool,
+    /// How long to wait on a locked SQLite database before failing, in milliseconds
+    #[arg(long, global = true, default_value_t = 5000)]
+    busy_timeout: u64,
     /// Filepath to the SQLite database
-    #[arg(short, long, default_value_t = String::from("corolla.sqlite3"))]
+    #[arg(short, long, global = true, default_value_t = String::from("corolla.sqlite3"))]
     db: String,
+    /// Include SQL error details in error responses
+    #[arg(long)]
+    debug: bool,
+    /// How long to wait for requests in flight to finish when shutting down, in milliseconds
+    #[arg(long, default_value_t = 10000)]
+    drain_timeout: u64,
     /// Write out the server process's PID to this file
     #[arg(long)]
     pid_file: Option<String>,
@@ -18,17 +28,61 @@ struct Args {
     #[arg(short, long, default_value_t = 50000)]
     port: i64,
     /// Base URL for API endpoints
-    #[arg(short, long, default_value_t = String::from(""))]
+    #[arg(short, long, global = true, default_value_t = String::from(""))]
     route: String,
     /// Filepath to the spec.json file
-    #[arg(short, long, default_value_t = String::from("spec.json"))]
+    #[arg(short, long, global = true, default_value_t = String::from("spec.json"))]
     spec: String,
     /// Filepath to static file directory
     #[arg(long, default_value_t = String::from("public"))]
     r#static: String,
-    /// Test mode?
+    /// Check the spec on a throwaway in-memory database, run its query examples, and exit
     #[arg(short, long)]
     test: bool,
+    /// Reload the spec when its file changes, as well as on SIGHUP on Unix
+    #[arg(long)]
+    watch_spec: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the test cases in the spec's `tests` section, each on a fresh in-memory database, and exit
+    Test,
+    /// Write the OpenAPI document describing the spec's endpoints, and exit
+    Openapi {
+        /// Filepath to write the document to; defaults to stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Run the spec's conversions and init statements on the database, and exit
+    Migrate {
+        /// Only list which conversions would run and which would be skipped
+        #[arg(long)]
+        plan: bool,
+        /// Run the conversions on a temporary copy of the database and print its resulting schema, leaving the database untouched
+        #[arg(long, conflicts_with = "plan")]
+        dry_run: bool,
+        /// Roll the database back to this version instead, e.g. 1.2.0, undoing conversions with their down_queries
+        #[arg(long)]
+        to: Option<corolla::Version>,
+    },
+    /// Generate a typed client for the spec's queries, and exit
+    Codegen {
+        #[command(subcommand)]
+        lang: CodegenLang,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CodegenLang {
+    /// A TypeScript module with one method per query
+    Ts {
+        /// Filepath to write the module to; defaults to stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
 }
 
 #[tokio::main]
@@ -43,21 +97,68 @@ async fn main() {
             .init(),
     }
     info!("corolla v{}", env!("CARGO_PKG_VERSION"));
-    if args.test {
-    } else {
-        match args.pid_file {
-            Some(path) => {
-                let mut file = File::create(&path).expect(&format!("could not open {}", path));
-                file.write_all(process::id().to_string().as_bytes())
-                    .expect(&format!("could not write PID to {}", path));
-            }
-            None => (),
+    if let Some(Command::Openapi { output }) = &args.command {
+        if let Err(e) = corolla::write_openapi(&args.spec, &args.route, output.as_deref()) {
+            error!("{e}");
+            process::exit(1)
+        }
+    } else if let Some(Command::Codegen {
+        lang: CodegenLang::Ts { output },
+    }) = &args.command
+    {
+        if let Err(e) = corolla::write_typescript(&args.spec, output.as_deref()) {
+            error!("{e}");
+            process::exit(1)
+        }
+    } else if let Some(Command::Migrate { plan, dry_run, to }) = &args.command {
+        let mode = match (*plan, *dry_run) {
+            (true, _) => corolla::MigrateMode::Plan,
+            (_, true) => corolla::MigrateMode::DryRun,
+            _ => corolla::MigrateMode::Apply,
         };
-        let res = corolla::run(&args.route, args.port, &args.db, &args.r#static, &args.spec).await;
+        let options = corolla::DbOptions::default()
+            .busy_timeout(Duration::from_millis(args.busy_timeout))
+            .allow_downgrade(args.allow_downgrade);
+        let res = corolla::migrate(&args.spec, &args.db, to.as_ref(), mode, options).await;
+        if let Err(e) = res {
+            error!("{e}");
+            process::exit(1)
+        }
+    } else if let Some(Command::Test) = args.command {
+        match corolla::run_tests(&args.spec).await {
+            Ok(_) => info!("all tests in {} passed", args.spec),
+            Err(e) => {
+                error!("{e}");
+                process::exit(1)
+            }
+        }
+    } else if args.test {
+        match corolla::self_check(&args.spec).await {
+            Ok(_) => info!("{} passed all checks", args.spec),
+            Err(e) => {
+                error!("{e}");
+                process::exit(1)
+            }
+        }
+    } else {
+        let res = corolla::run(&corolla::ServerConfig {
+            route_base: args.route,
+            port: args.port,
+            db_path: args.db,
+            static_path: args.r#static,
+            spec_path: args.spec,
+            debug: args.debug,
+            busy_timeout: Duration::from_millis(args.busy_timeout),
+            watch_spec: args.watch_spec,
+            drain_timeout: Duration::from_millis(args.drain_timeout),
+            pid_file: args.pid_file,
+            allow_downgrade: args.allow_downgrade,
+        })
+        .await;
         match res {
             Ok(_) => (),
             Err(e) => {
-                error!("{:?}", e);
+                error!("{e}");
                 process::exit(1)
             }
         }
@@ -37,11 +37,8 @@ pub async fn cleanup(delete_db: bool, proc: Option<&mut Child>) {
             .await
             .expect("could not execute mkdir");
     }
-    match proc {
-        Some(proc) => {
-            proc.kill().await.expect("could not kill server process");
-        }
-        None => (),
+    if let Some(proc) = proc {
+        proc.kill().await.expect("could not kill server process");
     }
 }
 
@@ -67,10 +64,7 @@ where
         .stdout(Stdio::null())
         .kill_on_drop(true)
         .spawn()
-        .expect(&format!(
-            "failed to run corolla with {}",
-            path.to_string_lossy()
-        ));
+        .unwrap_or_else(|_| panic!("failed to run corolla with {}", path.to_string_lossy()));
     // don't return until the server is fully started and ready to use
     while TcpStream::connect("localhost:50000").await.is_err() {
         info!("waiting to connect to corolla server");
@@ -78,3 +72,60 @@ where
     }
     proc
 }
+
+/// Picks a port that's free to listen on.
+pub fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("could not find a free port")
+        .port()
+}
+
+/// Waits for a corolla process to start listening on a port,
+/// and panics if it exits first or doesn't start within 30 seconds.
+pub async fn wait_for_server(proc: &mut Child, port: u16) {
+    let started = tokio::time::timeout(Duration::from_secs(30), async {
+        while TcpStream::connect(("127.0.0.1", port)).await.is_err() {
+            if let Some(status) = proc.try_wait().expect("could not check on corolla") {
+                panic!("corolla exited before it started listening: {status}");
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    assert!(started.is_ok(), "corolla didn't start listening on {port}");
+}
+
+/// Runs corolla in `--test` mode on a spec, and returns whether it passed.
+pub async fn self_check<S>(spec_path: &S) -> bool
+where
+    S: AsRef<OsStr> + ?Sized,
+{
+    Command::new(env!("CARGO_BIN_EXE_corolla"))
+        .arg("--test")
+        .arg("-s")
+        .arg(spec_path)
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await
+        .expect("failed to run corolla in test mode")
+        .success()
+}
+
+/// Runs `corolla test` on a spec, and returns whether its tests passed.
+pub async fn spec_tests<S>(spec_path: &S) -> bool
+where
+    S: AsRef<OsStr> + ?Sized,
+{
+    Command::new(env!("CARGO_BIN_EXE_corolla"))
+        .arg("test")
+        .arg("-s")
+        .arg(spec_path)
+        .stderr(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await
+        .expect("failed to run corolla test")
+        .success()
+}
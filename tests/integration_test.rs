@@ -1,7 +1,19 @@
-use common::{cleanup, server};
+use axum::http::HeaderMap;
+use common::{cleanup, free_port, self_check, server, spec_tests, wait_for_server};
 use pretty_assertions::assert_eq;
 use reqwest::StatusCode;
-use std::collections::HashMap;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::process::{Child, Command};
 
 mod common;
 
@@ -11,7 +23,7 @@ async fn integration_test() {
     let mut corolla = server("examples/example_spec.json").await;
     let inputs = ["sandringham", "beijing", "lombardy"];
     let client = reqwest::Client::new();
-    for x in inputs.iter() {
+    for (i, x) in inputs.iter().enumerate() {
         let mut body = HashMap::new();
         body.insert("vacation_spot", x);
         let res = client
@@ -20,12 +32,16 @@ async fn integration_test() {
             .send()
             .await
             .expect("could not make HTTP request");
+        let status = res.status();
+        let body: Value = res.json().await.expect("could not parse write result");
         assert_eq!(
-            res.status(),
+            status,
             StatusCode::OK,
             "HTTP request failed with message {:?}",
-            res.text().await
+            body
         );
+        assert_eq!(body["rows_affected"], 1);
+        assert_eq!(body["last_insert_rowid"], i + 1);
     }
     let res: Vec<Vec<String>> = reqwest::get("http://localhost:50000/test/read/read01")
         .await
@@ -36,12 +52,21 @@ async fn integration_test() {
     for (i, row) in res.iter().enumerate() {
         assert_eq!(row.len(), 1);
         if i == 0 {
-            assert_eq!(row.get(0).unwrap(), "vacation_spot");
+            assert_eq!(row.first().unwrap(), "vacation_spot");
         } else {
             let x = inputs.get(i - 1).unwrap();
-            assert_eq!(row.get(0).unwrap(), x);
+            assert_eq!(row.first().unwrap(), x);
         }
     }
+    let doc: Value = reqwest::get("http://localhost:50000/test/openapi.json")
+        .await
+        .expect("could not perform GET curl")
+        .json()
+        .await
+        .expect("could not parse OpenAPI document");
+    assert_eq!(doc["openapi"], "3.1.0");
+    assert!(doc["paths"]["/test/read/read01"]["get"].is_object());
+    assert!(doc["paths"]["/test/write/write01"]["post"].is_object());
     cleanup(false, Some(&mut corolla)).await;
     let mut corolla = server("examples/example_spec_with_conversions.json").await;
     let inputs = [
@@ -66,7 +91,7 @@ async fn integration_test() {
             res.text().await
         );
     }
-    let res: Vec<Vec<String>> = reqwest::get("http://localhost:50000/test/read/read01")
+    let res: Vec<Vec<Value>> = reqwest::get("http://localhost:50000/test/read/read01")
         .await
         .expect("could not perform GET curl")
         .json()
@@ -75,16 +100,450 @@ async fn integration_test() {
     let mut iter = res.iter();
     assert_eq!(
         iter.next().unwrap(),
-        &vec!["vacation_spot".to_string(), "notes".to_string()]
+        &vec![json!("vacation_spot"), json!("notes")]
     );
-    iter.next();
-    iter.next();
-    iter.next();
+    // rows written before the conversion have no notes
+    for _ in 0..3 {
+        assert_eq!(iter.next().unwrap().get(1).unwrap(), &Value::Null);
+    }
     for (i, row) in iter.enumerate() {
         assert_eq!(row.len(), 2);
         let (x, y) = inputs.get(i).unwrap();
-        assert_eq!(row.get(0).unwrap(), x);
+        assert_eq!(row.first().unwrap(), x);
         assert_eq!(row.get(1).unwrap(), y);
     }
+    // the legacy all-strings format can be requested per request
+    let res: Vec<Vec<String>> = client
+        .get("http://localhost:50000/test/read/read01")
+        .header("x-corolla-format", "strings")
+        .send()
+        .await
+        .expect("could not perform GET curl")
+        .json()
+        .await
+        .expect("could not parse JSON into expected structure");
+    assert_eq!(
+        res.get(1).unwrap(),
+        &vec!["sandringham".to_string(), String::new()]
+    );
     cleanup(true, Some(&mut corolla)).await;
 }
+
+#[tokio::test]
+async fn self_check_test() {
+    assert!(self_check("examples/example_spec.json").await);
+    assert!(self_check("examples/example_spec_with_conversions.json").await);
+    let dir = tempfile::tempdir().unwrap();
+    let spec_path = dir.path().join("spec.json");
+    let spec = json!({
+        "spec_version": [1, 0, 0],
+        "version": [1, 0, 0],
+        "init": ["create table t (x text not null);"],
+        "queries": {
+            "read": {},
+            "write": {
+                "write01": {
+                    "sql_template": "insert into t values (?);",
+                    "args": [{ "name": "x", "optional": true }],
+                    "examples": [{}]
+                }
+            }
+        },
+        "conversions": []
+    });
+    std::fs::write(&spec_path, spec.to_string()).unwrap();
+    assert!(!self_check(&spec_path).await);
+}
+
+#[tokio::test]
+async fn spec_tests_test() {
+    assert!(spec_tests("examples/example_spec.json").await);
+    // the conversion alters a table from init, which doesn't exist until init runs
+    assert!(spec_tests("examples/example_spec_with_conversions.json").await);
+    let dir = tempfile::tempdir().unwrap();
+    let spec_path = dir.path().join("spec.json");
+    let spec = json!({
+        "spec_version": [1, 0, 0],
+        "version": [1, 0, 0],
+        "init": ["create table t (x text);"],
+        "queries": {
+            "read": {
+                "read01": { "sql_template": "select x from t;", "args": [], "cols": ["x"] }
+            },
+            "write": {}
+        },
+        "conversions": [],
+        "tests": [
+            { "name": "empty", "steps": [{ "type": "read", "query": "read01", "rows": [["a"]] }] }
+        ]
+    });
+    std::fs::write(&spec_path, spec.to_string()).unwrap();
+    assert!(!spec_tests(&spec_path).await);
+}
+
+#[test]
+fn exported_types_test() {
+    // everything reachable from a spec's public fields can be named through corolla
+    let spec = corolla::read_spec("examples/example_spec_with_conversions.json").unwrap();
+    let queries: &corolla::Queries = &spec.queries;
+    let conversion: &corolla::Conversion = &spec.conversions[0];
+    assert_eq!(conversion.queries.len(), 1);
+    let test: &corolla::SpecTest = &spec.tests[0];
+    let step: &corolla::TestStep = &test.steps[0];
+    let item: &corolla::BatchItem = &step.call;
+    assert_eq!(item.r#type, corolla::BatchItemType::Write);
+    let arg: &corolla::Arg = &queries.write["write01"].args[0];
+    let regex: Option<&corolla::Pattern> = arg.regex.as_ref();
+    assert!(regex.is_none());
+    assert_eq!(
+        arg.coerce(&json!("avon")),
+        Ok(corolla::SqlValue::Text("avon".to_owned()))
+    );
+    assert_eq!(
+        queries.read["read01"].blob_encoding,
+        corolla::BlobEncoding::default()
+    );
+    assert!(matches!(
+        corolla::read_spec("examples/missing.json"),
+        Err(corolla::SpecError::File(_))
+    ));
+    let body: corolla::ErrorBody =
+        corolla::Error::QueryDoesNotExist("read02".to_owned()).body(false);
+    assert_eq!(
+        serde_json::to_value(body).unwrap()["code"],
+        json!("query_not_found")
+    );
+}
+
+#[tokio::test]
+async fn embedded_router_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("embedded.sqlite3");
+    let spec = corolla::read_spec("examples/example_spec.json").unwrap();
+    let db = corolla::DB::from_spec(db.to_str().unwrap(), &spec, corolla::DbOptions::default())
+        .await
+        .unwrap();
+    // mount corolla inside another app, with the app's own routes and middleware
+    let app = axum::Router::new()
+        .route("/health", axum::routing::get(|| async { "ok" }))
+        .nest(
+            "/corolla",
+            corolla::RouterBuilder::new(db).debug(true).build(),
+        )
+        .layer(axum::middleware::map_response(
+            |mut res: axum::response::Response| async {
+                res.headers_mut()
+                    .insert("x-app", axum::http::HeaderValue::from_static("mine"));
+                res
+            },
+        ));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("http://{addr}/corolla/write/write01"))
+        .json(&json!({ "vacation_spot": "avon" }))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-app"], "mine");
+    // malformed requests get the same JSON error bodies as the rest
+    let res = client
+        .post(format!("http://{addr}/corolla/write/write01"))
+        .header("content-type", "application/json")
+        .body("not json")
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_body");
+    let res = client
+        .post(format!(
+            "http://{addr}/corolla/write/write01?all_or_nothing=maybe"
+        ))
+        .json(&json!({ "vacation_spot": "avon" }))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["code"], "invalid_query_string");
+    let rows: Value = reqwest::get(format!("http://{addr}/corolla/read/read01"))
+        .await
+        .expect("could not perform GET curl")
+        .json()
+        .await
+        .expect("could not parse JSON into expected structure");
+    assert_eq!(rows, json!([["vacation_spot"], ["avon"]]));
+    let health = reqwest::get(format!("http://{addr}/health"))
+        .await
+        .expect("could not perform GET curl")
+        .text()
+        .await
+        .unwrap();
+    assert_eq!(health, "ok");
+}
+
+/// Vetoes writes of forbidden vacation spots and capitalizes the ones it reads.
+struct VacationHook {
+    writes: Arc<AtomicUsize>,
+}
+
+#[corolla::async_trait]
+impl corolla::QueryHook for VacationHook {
+    async fn before_write(
+        &self,
+        _query: &str,
+        headers: &HeaderMap,
+        args: &mut corolla::Args,
+    ) -> Result<(), corolla::Error> {
+        if args["vacation_spot"] == "mordor" && !headers.contains_key("x-ring-bearer") {
+            return Err(corolla::Error::Rejected {
+                status: StatusCode::FORBIDDEN,
+                message: "one does not simply walk into mordor".to_owned(),
+            });
+        }
+        args.insert(
+            "vacation_spot".to_owned(),
+            json!(args["vacation_spot"].as_str().unwrap().trim()),
+        );
+        Ok(())
+    }
+
+    async fn after_read(
+        &self,
+        _query: &str,
+        _headers: &HeaderMap,
+        _args: &corolla::Args,
+        rows: &mut Vec<Vec<Value>>,
+    ) -> Result<(), corolla::Error> {
+        for row in rows.iter_mut().skip(1) {
+            row[0] = json!(row[0].as_str().unwrap().to_uppercase());
+        }
+        Ok(())
+    }
+
+    async fn after_write(
+        &self,
+        _query: &str,
+        _headers: &HeaderMap,
+        _args: &corolla::Args,
+        _result: &mut corolla::WriteResult,
+    ) -> Result<(), corolla::Error> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn query_hooks_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("hooks.sqlite3");
+    let spec = corolla::read_spec("examples/example_spec.json").unwrap();
+    let db = corolla::DB::from_spec(db.to_str().unwrap(), &spec, corolla::DbOptions::default())
+        .await
+        .unwrap();
+    let writes = Arc::new(AtomicUsize::new(0));
+    let app = corolla::RouterBuilder::new(db)
+        .hook(VacationHook {
+            writes: writes.clone(),
+        })
+        .build();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let res = client
+        .post(format!("http://{addr}/write/write01"))
+        .json(&json!({ "vacation_spot": " avon " }))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = client
+        .post(format!("http://{addr}/write/write01"))
+        .json(&json!({ "vacation_spot": "mordor" }))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["code"], "rejected");
+    assert_eq!(body["message"], "one does not simply walk into mordor");
+    // hooks also run for the items of a batch
+    let res = client
+        .post(format!("http://{addr}/batch"))
+        .json(&json!([
+            { "type": "write", "query": "write01", "args": { "vacation_spot": "shire" } },
+            { "type": "write", "query": "write01", "args": { "vacation_spot": "mordor" } }
+        ]))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    // unless the writes aren't all or nothing, where only the vetoed ones are skipped
+    let res = client
+        .post(format!("http://{addr}/write/write01?all_or_nothing=false"))
+        .json(&json!([{ "vacation_spot": "mordor" }, { "vacation_spot": "bree" }]))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body[0]["ok"], false);
+    assert_eq!(body[0]["error"]["code"], "rejected");
+    assert_eq!(body[1]["ok"], true);
+    let res = client
+        .post(format!("http://{addr}/batch"))
+        .json(&json!({
+            "items": [
+                { "type": "write", "query": "write01", "args": { "vacation_spot": "mordor" } },
+                { "type": "read", "query": "read01" }
+            ],
+            "all_or_nothing": false
+        }))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body[0]["error"]["code"], "rejected");
+    assert_eq!(
+        body[1]["result"],
+        json!([["vacation_spot"], ["AVON"], ["BREE"]])
+    );
+    let res = client
+        .post(format!("http://{addr}/batch"))
+        .header("x-ring-bearer", "frodo")
+        .json(&json!([
+            { "type": "write", "query": "write01", "args": { "vacation_spot": "mordor" } },
+            { "type": "read", "query": "read01" }
+        ]))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(
+        body[1]["result"],
+        json!([["vacation_spot"], ["AVON"], ["BREE"], ["MORDOR"]])
+    );
+    assert_eq!(writes.load(Ordering::Relaxed), 3);
+}
+
+#[tokio::test]
+async fn graceful_shutdown_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("corolla.sqlite3");
+    let pid_file = dir.path().join("corolla.pid");
+    let port = free_port();
+    let mut proc = Command::new(env!("CARGO_BIN_EXE_corolla"))
+        .args(["-s", "examples/example_spec.json", "-p", &port.to_string()])
+        .arg("--pid-file")
+        .arg(&pid_file)
+        .arg("-d")
+        .arg(&db)
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("could not run corolla");
+    wait_for_server(&mut proc, port).await;
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    assert_eq!(pid, proc.id().unwrap().to_string());
+    let res = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{port}/write/write01"))
+        .json(&json!({ "vacation_spot": "avon" }))
+        .send()
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::OK);
+    let killed = Command::new("kill")
+        .args(["-TERM", &pid])
+        .status()
+        .await
+        .unwrap();
+    assert!(killed.success());
+    assert!(proc.wait().await.unwrap().success());
+    assert!(!pid_file.exists());
+    // the WAL was checkpointed into the database file
+    let wal = dir.path().join("corolla.sqlite3-wal");
+    assert!(!wal.exists() || std::fs::metadata(&wal).unwrap().len() == 0);
+}
+
+/// Starts a server for a spec on a free port, and returns it and its port.
+async fn reload_server(spec_path: &Path, db: &Path, watch_spec: bool) -> (Child, u16) {
+    let port = free_port();
+    let mut proc = Command::new(env!("CARGO_BIN_EXE_corolla"))
+        .arg("-s")
+        .arg(spec_path)
+        .arg("-d")
+        .arg(db)
+        .args(["-p", &port.to_string()])
+        .args(watch_spec.then_some("--watch-spec"))
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("could not run corolla");
+    wait_for_server(&mut proc, port).await;
+    (proc, port)
+}
+
+/// Adds a `read02` query to the spec on disk.
+fn add_read02(spec_path: &Path) {
+    let mut spec: Value =
+        serde_json::from_str(&std::fs::read_to_string(spec_path).unwrap()).unwrap();
+    spec["queries"]["read"]["read02"] = json!({
+        "sql_template": "select count(*) as n from t;",
+        "args": [],
+        "cols": ["n"]
+    });
+    std::fs::write(spec_path, spec.to_string()).unwrap();
+}
+
+/// Returns whether a server serves a read query, waiting up to 30 seconds for it to.
+async fn serves(port: u16, query: &str) -> bool {
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while reqwest::get(format!("http://127.0.0.1:{port}/read/{query}"))
+            .await
+            .expect("could not make HTTP request")
+            .status()
+            != StatusCode::OK
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .is_ok()
+}
+
+#[tokio::test]
+async fn watch_spec_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec_path = dir.path().join("spec.json");
+    std::fs::copy("examples/example_spec.json", &spec_path).unwrap();
+    let (_proc, port) = reload_server(&spec_path, &dir.path().join("db.sqlite3"), true).await;
+    let res = reqwest::get(format!("http://127.0.0.1:{port}/read/read02"))
+        .await
+        .expect("could not make HTTP request");
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    add_read02(&spec_path);
+    assert!(serves(port, "read02").await);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sighup_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let spec_path = dir.path().join("spec.json");
+    std::fs::copy("examples/example_spec.json", &spec_path).unwrap();
+    let (proc, port) = reload_server(&spec_path, &dir.path().join("db.sqlite3"), false).await;
+    add_read02(&spec_path);
+    let hung_up = Command::new("kill")
+        .args(["-HUP", &proc.id().unwrap().to_string()])
+        .status()
+        .await
+        .unwrap();
+    assert!(hung_up.success());
+    assert!(serves(port, "read02").await);
+}